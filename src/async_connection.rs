@@ -0,0 +1,92 @@
+use std::{
+    io::{Error, ErrorKind},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    sync::{
+        mpsc::{self, UnboundedReceiver},
+        Mutex,
+    },
+};
+
+use crate::{
+    bulb::Bulb,
+    connection::{ConnectionOptions, Notification, RequestIds},
+    event::{BulbEvent, Subscribers},
+    frame::FrameBuffer,
+};
+
+/// Non-blocking counterpart of [`BulbConnection`](crate::connection::BulbConnection).
+/// Offers the same methods as async fns with the same validation and errors.
+pub struct AsyncBulbConnection<T: AsyncRead + AsyncWrite + Unpin> {
+    pub bulb: Bulb,
+    pub connection: Mutex<T>,
+    pub(crate) ids: RequestIds,
+    pub(crate) subscribers: Subscribers,
+    pub(crate) frames: FrameBuffer,
+    pub(crate) response_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncBulbConnection<T> {
+    pub fn from_connection(bulb: Bulb, connection: T) -> Self {
+        AsyncBulbConnection {
+            bulb,
+            connection: Mutex::new(connection),
+            ids: RequestIds::new(),
+            subscribers: Subscribers::default(),
+            frames: FrameBuffer::new(),
+            response_timeout: None,
+            write_timeout: None,
+        }
+    }
+
+    /// See [`BulbConnection::with_response_timeout`](crate::connection::BulbConnection::with_response_timeout).
+    /// Here the deadline cuts short any pending read, whatever the transport.
+    pub fn with_response_timeout(mut self, timeout: Duration) -> Self {
+        self.response_timeout = Some(timeout);
+        self
+    }
+
+    /// See [`BulbConnection::notifications`](crate::connection::BulbConnection::notifications).
+    pub fn notifications(&mut self) -> UnboundedReceiver<Notification> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers
+            .listen_notifications(move |n| sender.send(n.clone()).is_ok());
+        receiver
+    }
+
+    /// See [`BulbConnection::events`](crate::connection::BulbConnection::events).
+    /// The connection doesn't reconnect, so it reports state changes only.
+    pub fn events(&mut self) -> UnboundedReceiver<BulbEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers
+            .listen_events(move |event| sender.send(event.clone()).is_ok());
+        receiver
+    }
+}
+
+pub type AsyncTcpConnection = AsyncBulbConnection<TcpStream>;
+
+impl AsyncTcpConnection {
+    pub async fn new(bulb: Bulb) -> Result<Self, Error> {
+        AsyncTcpConnection::with_options(bulb, &ConnectionOptions::new()).await
+    }
+
+    pub async fn with_options(bulb: Bulb, options: &ConnectionOptions) -> Result<Self, Error> {
+        let connect = TcpStream::connect(&bulb.ip_address);
+        let stream = match options.get_connect_timeout() {
+            Some(timeout) => tokio::time::timeout(timeout, connect)
+                .await
+                .map_err(|_| Error::from(ErrorKind::TimedOut))??,
+            None => connect.await?,
+        };
+        let mut connection = AsyncBulbConnection::from_connection(bulb, stream);
+        connection.response_timeout = options.get_response_timeout();
+        connection.write_timeout = options.get_write_timeout();
+        Ok(connection)
+    }
+}
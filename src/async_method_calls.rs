@@ -0,0 +1,309 @@
+use enum_iterator::IntoEnumIterator;
+use std::{collections::HashMap, io};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::{self, Instant},
+};
+
+use crate::{
+    async_connection::AsyncBulbConnection,
+    connection::{
+        CronResponse, CronType, MethodCallError, MethodCallResponse, MusicMode, StringVecResponse,
+    },
+    light_methods::{control_methods, light_methods},
+    method::Method,
+    method_calls::{
+        apply_call, create_message, cron_type_args, decode_names, get_prop_args, io_error,
+        music_args, next_reply, parse_response, reply_id, MethodArg,
+    },
+    property::{prop_names, zip_props, BulbState, Property},
+};
+
+impl<C: AsyncRead + AsyncWrite + Unpin> AsyncBulbConnection<C> {
+    async fn call_method<T>(
+        &mut self,
+        method: Method,
+        args: Result<Vec<MethodArg>, MethodCallError>,
+    ) -> Result<T, MethodCallError>
+    where
+        for<'a> T: MethodCallResponse<'a>,
+    {
+        match self.checked_call(method, args).await {
+            Err(err) => Err(err.in_call(method, &self.bulb)),
+            result => result,
+        }
+    }
+
+    async fn checked_call<T>(
+        &mut self,
+        method: Method,
+        args: Result<Vec<MethodArg>, MethodCallError>,
+    ) -> Result<T, MethodCallError>
+    where
+        for<'a> T: MethodCallResponse<'a>,
+    {
+        let args = args?;
+        if !self.bulb.support.contains(&method) {
+            return Err(MethodCallError::UnsupportedMethod);
+        }
+
+        let mut conn = self.connection.lock().await;
+
+        let id = self.ids.next_id();
+        let message = create_message(id, &method, args.clone());
+
+        let write = conn.write_all(message.as_bytes());
+        match self.write_timeout {
+            Some(timeout) => time::timeout(timeout, write)
+                .await
+                .map_err(|_| MethodCallError::Timeout)?,
+            None => write.await,
+        }
+        .map_err(io_error)?;
+
+        let deadline = self
+            .response_timeout
+            .map(|timeout| Instant::now() + timeout);
+        let mut buf = [0; 2048];
+        loop {
+            let (bulb, subscribers) = (&mut self.bulb, &mut self.subscribers);
+            let reply = next_reply(&mut self.frames, |n| subscribers.deliver(bulb, n))?;
+            if let Some(reply) = reply {
+                match reply_id(&reply) {
+                    Some(reply_id) if reply_id != id => continue,
+                    _ => {
+                        let result = parse_response(&reply, id);
+                        if result.is_ok() {
+                            apply_call(&mut self.bulb, method, &args);
+                        }
+                        return result;
+                    }
+                }
+            }
+
+            let read = conn.read(&mut buf);
+            let read = match deadline {
+                Some(deadline) => time::timeout_at(deadline, read)
+                    .await
+                    .map_err(|_| MethodCallError::Timeout)?,
+                None => read.await,
+            }
+            .map_err(io_error)?;
+            if read == 0 {
+                return Err(MethodCallError::IOError(
+                    io::ErrorKind::UnexpectedEof.into(),
+                ));
+            }
+            self.frames.extend(&buf[..read]);
+        }
+    }
+
+    /// See [`BulbConnection::get_prop`](crate::connection::BulbConnection::get_prop).
+    pub async fn get_prop(&mut self, props: &[&str]) -> Result<StringVecResponse, MethodCallError> {
+        let mut response = self
+            .call_method(Method::GetProp, get_prop_args(props))
+            .await?;
+        decode_names(props.iter().copied(), &mut response);
+        Ok(response)
+    }
+
+    /// See [`BulbConnection::get_props`](crate::connection::BulbConnection::get_props).
+    pub async fn get_props(
+        &mut self,
+        props: &[Property],
+    ) -> Result<HashMap<Property, String>, MethodCallError> {
+        let response = self.get_prop(&prop_names(props)).await?;
+        zip_props(props, response).map_err(|err| err.in_call(Method::GetProp, &self.bulb))
+    }
+
+    /// See [`BulbConnection::get_state`](crate::connection::BulbConnection::get_state).
+    pub async fn get_state(&mut self) -> Result<BulbState, MethodCallError> {
+        let props: Vec<Property> = Property::into_enum_iter().collect();
+        let values = self.get_props(&props).await?;
+        BulbState::parse(&values)
+            .ok_or_else(|| MethodCallError::ParseError.in_call(Method::GetProp, &self.bulb))
+    }
+
+    pub async fn cron_get(
+        &mut self,
+        cron_type: &CronType,
+    ) -> Result<CronResponse, MethodCallError> {
+        self.call_method(Method::CronGet, Ok(cron_type_args(cron_type)))
+            .await
+    }
+
+    pub async fn set_music(
+        &mut self,
+        mode: MusicMode<'_>,
+    ) -> Result<StringVecResponse, MethodCallError> {
+        self.call_method(Method::SetMusic, Ok(music_args(mode)))
+            .await
+    }
+
+    light_methods!(async call_method -> Result<StringVecResponse, MethodCallError>, bulb);
+    control_methods!(async call_method -> Result<StringVecResponse, MethodCallError>, bulb);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio_test::io::{Builder, Mock};
+
+    use crate::{
+        async_connection::AsyncBulbConnection,
+        bulb::Bulb,
+        connection::{CronType, MethodCallError, TransitionMode},
+        event::BulbEvent,
+        lightmode::LightMode,
+        method::Method,
+        power::Power,
+        rgb::RGB,
+        test_utils::bulb_with_methods,
+    };
+
+    const TEST_OK_VAL: &[u8] = b"{\"id\":1, \"result\":[\"ok\"]}\r\n";
+
+    fn conn_with_method(method: Method, mock: Mock) -> AsyncBulbConnection<Mock> {
+        let mut support = std::collections::HashSet::new();
+        support.insert(method);
+
+        AsyncBulbConnection::from_connection(
+            Bulb {
+                id: "".to_string(),
+                model: "".to_string(),
+                fw_ver: "".to_string(),
+                support,
+                power: Power::Off,
+                bright: 0,
+                color_mode: LightMode::ColorTemperature(8),
+                name: "".to_string(),
+                ip_address: "".to_string(),
+            },
+            mock,
+        )
+    }
+
+    #[tokio::test]
+    async fn set_rgb_test() {
+        let mock = Builder::new()
+            .write(b"{\"id\":1,\"method\":\"set_rgb\",\"params\":[255,\"smooth\",500]}\r\n")
+            .read(TEST_OK_VAL)
+            .build();
+
+        let mut conn = conn_with_method(Method::SetRgb, mock);
+
+        let result = conn
+            .set_rgb(
+                &RGB { r: 0, g: 0, b: 255 },
+                TransitionMode::Smooth(Duration::from_millis(500)),
+            )
+            .await;
+        assert_eq!(result.unwrap().result, vec!["ok".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn get_prop_test() {
+        let mock = Builder::new()
+            .write(b"{\"id\":1,\"method\":\"get_prop\",\"params\":[\"power\",\"bright\"]}\r\n")
+            .read(b"{\"id\":1, \"result\":[\"on\", \"100\"]}\r\n")
+            .build();
+
+        let mut conn = conn_with_method(Method::GetProp, mock);
+
+        let result = conn.get_prop(&["power", "bright"]).await.unwrap();
+        assert_eq!(result.result, vec!["on".to_string(), "100".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn cron_get_test() {
+        let mock = Builder::new()
+            .write(b"{\"id\":1,\"method\":\"cron_get\",\"params\":[0]}\r\n")
+            .read(b"{\"id\":1, \"result\":[{\"type\": 0, \"delay\": 15, \"mix\": 0}]}\r\n")
+            .build();
+
+        let mut conn = conn_with_method(Method::CronGet, mock);
+
+        let result = conn.cron_get(&CronType::PowerOff).await.unwrap();
+        assert_eq!(result.result.first().unwrap().delay, 15);
+    }
+
+    #[tokio::test]
+    async fn error_response_test() {
+        let mock = Builder::new()
+            .write(b"{\"id\":1,\"method\":\"toggle\",\"params\":[]}\r\n")
+            .read(b"{\"id\":1, \"error\":{\"code\":-1, \"message\":\"unsupported method\"}}\r\n")
+            .build();
+
+        let mut conn = conn_with_method(Method::Toggle, mock);
+
+        let result = conn.toggle().await;
+        assert!(matches!(
+            result.unwrap_err().kind(),
+            MethodCallError::ErrorResponse(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn validation_test() {
+        let mut conn = conn_with_method(Method::SetCtAbx, Builder::new().build());
+
+        let result = conn.set_ct_abx(1000, TransitionMode::Sudden).await;
+        assert!(matches!(
+            result.unwrap_err().kind(),
+            MethodCallError::BadRequest
+        ));
+
+        let result = conn.toggle().await;
+        assert!(matches!(
+            result.unwrap_err().kind(),
+            MethodCallError::UnsupportedMethod
+        ));
+    }
+
+    #[tokio::test]
+    async fn notification_before_reply_test() {
+        let mock = Builder::new()
+            .write(b"{\"id\":1,\"method\":\"toggle\",\"params\":[]}\r\n")
+            .read(b"{\"method\":\"props\",\"params\":{\"power\":\"on\"}}\r\n")
+            .read(TEST_OK_VAL)
+            .build();
+
+        let mut conn = conn_with_method(Method::Toggle, mock);
+        let mut notifications = conn.notifications();
+        let mut events = conn.events();
+
+        assert!(conn.toggle().await.is_ok());
+
+        let notification = notifications.try_recv().unwrap();
+        assert_eq!(notification.params.get("power").unwrap(), "on");
+        assert_eq!(events.try_recv().unwrap(), BulbEvent::Power(Power::On));
+    }
+
+    #[tokio::test]
+    async fn fragmented_reply_test() {
+        let mock = Builder::new()
+            .write(b"{\"id\":1,\"method\":\"toggle\",\"params\":[]}\r\n")
+            .read(b"{\"id\":1, \"res")
+            .read(b"ult\":[\"ok\"]}\r\n")
+            .build();
+
+        let mut conn = conn_with_method(Method::Toggle, mock);
+
+        assert!(conn.toggle().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn response_timeout_test() {
+        let (client, _bulb_side) = tokio::io::duplex(64);
+        let bulb = bulb_with_methods(&[Method::Toggle]);
+        let mut conn = AsyncBulbConnection::from_connection(bulb, client)
+            .with_response_timeout(Duration::from_millis(20));
+
+        assert!(matches!(
+            conn.toggle().await.unwrap_err().kind(),
+            MethodCallError::Timeout
+        ));
+    }
+}
@@ -1,11 +1,13 @@
 use crate::lightmode::LightMode;
 use crate::method::Method;
+use crate::name::decode_name;
 use crate::power::Power;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::convert::TryFrom;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Bulb {
     // The ID of a Yeelight WiFi LED device that uniquely identifies a Yeelight WiFi LED device.
     pub id: String,
@@ -29,8 +31,8 @@ pub struct Bulb {
     pub color_mode: LightMode,
 
     // Name of the device. User can use “set_name” to store the name on the device.
-    // The maximum length is 64 bytes. If none-ASCII character is used, it is suggested to
-    // BASE64 the name first and then use “set_name” to store it on device.
+    // The maximum length is 64 bytes. Non-ASCII names are stored BASE64 encoded,
+    // “set_name” encodes them and they are decoded here.
     pub name: String,
 
     pub ip_address: String,
@@ -40,25 +42,46 @@ fn parse_to_hashmap(search_response: &str) -> HashMap<String, String> {
     let mut map = HashMap::new();
     search_response
         .split("\r\n")
-        .into_iter()
         .flat_map(|line| {
             let split_line: Vec<&str> = line.split(": ").collect();
 
-            let key = split_line.first();
-            if key.is_none() {
-                return None;
-            }
+            let key = split_line.first()?;
 
-            let val = split_line.iter().skip(1).map(|s| *s).collect();
-            Some((key.unwrap().clone(), val))
+            let val = split_line.iter().skip(1).copied().collect();
+            Some((*key, val))
         })
         .for_each(|pair| {
             map.insert(pair.0.to_string(), pair.1);
         });
-    return map;
+    map
 }
 
 impl Bulb {
+    /// Updates the state with property values as `get_prop` and `props`
+    /// notifications report them. Unknown properties are ignored, and so
+    /// are colour values that don't make up a complete light mode.
+    pub fn apply_props(&mut self, props: &HashMap<String, String>) {
+        if let Some(power) = props.get("power").and_then(|p| Power::try_from(p).ok()) {
+            self.power = power;
+        }
+        if let Some(bright) = props.get("bright").and_then(|b| b.parse::<u8>().ok()) {
+            self.bright = bright;
+        }
+        if let Some(name) = props.get("name") {
+            self.name = decode_name(name);
+        }
+
+        let mut mode_props = self.color_mode.to_props();
+        for key in ["color_mode", "rgb", "ct", "hue", "sat"].iter() {
+            if let Some(value) = props.get(*key) {
+                mode_props.insert(key.to_string(), value.clone());
+            }
+        }
+        if let Some(mode) = LightMode::parse(&mode_props) {
+            self.color_mode = mode;
+        }
+    }
+
     pub fn parse(search_response: &str) -> Option<Bulb> {
         let response_map = parse_to_hashmap(search_response);
         let id = response_map.get("id");
@@ -66,17 +89,15 @@ impl Bulb {
         let fw_ver = response_map.get("fw_ver");
         let support = response_map.get("support").map(|s| {
             s.split(" ")
-                .flat_map(|s| Method::try_from(s))
+                .flat_map(Method::try_from)
                 .collect::<HashSet<Method>>()
         });
         let power = response_map
             .get("power")
-            .map(|s| Power::try_from(s).ok())
-            .flatten();
+            .and_then(|s| Power::try_from(s).ok());
         let brightness = response_map
             .get("bright")
-            .map(|s| s.parse::<u8>().ok())
-            .flatten();
+            .and_then(|s| s.parse::<u8>().ok());
 
         let light_mode = LightMode::parse(&response_map);
 
@@ -84,8 +105,7 @@ impl Bulb {
 
         let ip = response_map
             .get("Location")
-            .map(|s| s.split("//").nth(1))
-            .flatten();
+            .and_then(|s| s.split("//").nth(1));
 
         if let (
             Some(model),
@@ -106,9 +126,9 @@ impl Bulb {
                 fw_ver: fw_ver.clone(),
                 id: id.clone(),
                 model: model.clone(),
-                name: name.clone(),
-                power: power,
-                support: support,
+                name: decode_name(name),
+                power,
+                support,
                 ip_address: ip.to_string(),
             };
             Some(bulb)
@@ -120,7 +140,9 @@ impl Bulb {
 
 #[cfg(test)]
 mod tests {
-    use crate::{bulb::Bulb, lightmode::LightMode, method::Method};
+    use std::collections::HashMap;
+
+    use crate::{bulb::Bulb, lightmode::LightMode, method::Method, power::Power, rgb::RGB};
 
     #[test]
     fn bulb_parse_test() {
@@ -175,7 +197,55 @@ mod tests {
         ];
 
         for method in methods {
-            assert!(bulb.support.contains(&method));
+            assert!(bulb.support.contains(method));
         }
     }
+
+    #[test]
+    fn apply_props_test() {
+        let mut bulb = Bulb::parse(
+            "Location: yeelight://192.168.1.239:55443\r\n\
+            id: 0x000000000015243f\r\n\
+            model: color\r\n\
+            fw_ver: 18\r\n\
+            support: get_prop\r\n\
+            power: on\r\n\
+            bright: 100\r\n\
+            color_mode: 2\r\n\
+            ct: 4000\r\n\
+            name: lamp\r\n",
+        )
+        .unwrap();
+        let props = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        bulb.apply_props(&props(&[
+            ("power", "off"),
+            ("bright", "20"),
+            ("color_mode", "1"),
+            ("rgb", "255"),
+            ("name", "5bGF6ZaT"),
+        ]));
+        assert_eq!(bulb.power, Power::Off);
+        assert_eq!(bulb.bright, 20);
+        assert_eq!(
+            bulb.color_mode,
+            LightMode::Color(RGB { r: 0, g: 0, b: 255 })
+        );
+        assert_eq!(bulb.name, "居間");
+
+        // A colour temperature alone doesn't leave colour mode.
+        bulb.apply_props(&props(&[("ct", "3000")]));
+        assert_eq!(
+            bulb.color_mode,
+            LightMode::Color(RGB { r: 0, g: 0, b: 255 })
+        );
+
+        bulb.apply_props(&props(&[("color_mode", "2"), ("ct", "3000")]));
+        assert_eq!(bulb.color_mode, LightMode::ColorTemperature(3000));
+    }
 }
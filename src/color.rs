@@ -0,0 +1,842 @@
+//! Conversions between [`RGB`], [`HSV`] and other colour spaces. `RGB` is
+//! taken to be sRGB.
+//!
+//! The bulb keeps colour and brightness apart, so converting to `HSV` drops
+//! the value (brightness) of the colour and converting from it gives the
+//! colour at full value. The other spaces use floating point and convert
+//! back to the same `RGB`.
+//!
+//! `RGB` and `HSV` also parse from the ways colours are usually written:
+//! `#rgb`, `#rrggbb`, `rgb(…)`, `hsl(…)`, `hsv(…)`, the integer the bulb
+//! reports for `rgb`, and the CSS colour names.
+//!
+//! ```
+//! use libyee::{lightmode::HSV, rgb::RGB};
+//!
+//! let orange: RGB = "rgb(255, 136, 0)".parse().unwrap();
+//! assert_eq!("#ff8800".parse(), Ok(orange));
+//! assert_eq!("hsl(32, 100%, 50%)".parse(), Ok(orange));
+//! assert_eq!(format!("{:#}", orange), "#ff8800");
+//!
+//! let hsv: HSV = "hsv(32, 100)".parse().unwrap();
+//! assert_eq!(hsv.to_string(), "hsv(32, 100)");
+//! ```
+
+mod names;
+
+use std::{convert::TryFrom, error, fmt, str::FromStr};
+
+use crate::{lightmode::HSV, rgb::RGB};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColorError {
+    /// The named component is outside its range.
+    OutOfRange(&'static str),
+    /// Not a colour written as `#rrggbb` or `#rgb`.
+    InvalidHex(String),
+    /// There was nothing to parse.
+    Empty,
+    /// Not written in any of the forms a colour parses from.
+    Unknown(String),
+    /// A colour function given too few or too many components.
+    ComponentCount {
+        function: &'static str,
+        found: usize,
+    },
+    /// A component of a colour function isn't a number in its range.
+    InvalidComponent {
+        component: &'static str,
+        value: String,
+    },
+}
+
+impl fmt::Display for ColorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColorError::OutOfRange(component) => write!(f, "{} is out of range", component),
+            ColorError::InvalidHex(hex) => {
+                write!(f, "{:?} is not a colour as #rrggbb or #rgb", hex)
+            }
+            ColorError::Empty => write!(f, "no colour given"),
+            ColorError::Unknown(color) => write!(
+                f,
+                "{:?} is not a hex colour, rgb(), hsl(), hsv(), integer or colour name",
+                color
+            ),
+            ColorError::ComponentCount { function, found } => {
+                write!(f, "{}() can't take {} components", function, found)
+            }
+            ColorError::InvalidComponent { component, value } => {
+                write!(f, "{:?} is not a valid {}", value, component)
+            }
+        }
+    }
+}
+
+impl error::Error for ColorError {}
+
+/// Hue in degrees from 0 up to 360, saturation and lightness as
+/// percentages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hsl {
+    pub hue: f64,
+    pub saturation: f64,
+    pub lightness: f64,
+}
+
+/// CIE 1931 chromaticity, the colour without its luminance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CieXy {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// CIE L*a*b*, relative to the D65 white point. Equal steps in it look
+/// about equally far apart, which makes it the space to fade through.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lab {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+}
+
+/// A colour temperature in Kelvin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Kelvin(pub u16);
+
+/// The D65 white point, sRGB's white.
+const D65: CieXy = CieXy {
+    x: 0.3127,
+    y: 0.3290,
+};
+
+fn channel(value: f64) -> u8 {
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+fn unit(value: u8) -> f64 {
+    f64::from(value) / 255.0
+}
+
+/// Hue in degrees, chroma and the largest component of a colour.
+pub(crate) fn hue_chroma(rgb: RGB) -> (f64, f64, f64) {
+    let (r, g, b) = (unit(rgb.r), unit(rgb.g), unit(rgb.b));
+    let max = r.max(g).max(b);
+    let chroma = max - r.min(g).min(b);
+
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / chroma + 2.0)
+    } else {
+        60.0 * ((r - g) / chroma + 4.0)
+    };
+    (hue, chroma, max)
+}
+
+/// The colour of the given hue and chroma, lifted by `lift` on every
+/// component.
+fn from_hue_chroma(hue: f64, chroma: f64, lift: f64) -> RGB {
+    let sector = hue / 60.0;
+    let x = chroma * (1.0 - (sector.rem_euclid(2.0) - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    RGB {
+        r: channel(r + lift),
+        g: channel(g + lift),
+        b: channel(b + lift),
+    }
+}
+
+/// Saturation and value go from 0 to 1.
+pub(crate) fn from_hsv(hue: f64, saturation: f64, value: f64) -> RGB {
+    let chroma = value * saturation;
+    from_hue_chroma(hue, chroma, value - chroma)
+}
+
+impl RGB {
+    /// The colour as `#rrggbb`.
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    /// The colour as `rgb(255, 136, 0)`, which parses back.
+    pub fn to_css(&self) -> String {
+        format!("rgb({}, {}, {})", self.r, self.g, self.b)
+    }
+
+    /// Reads a colour written as `#rrggbb` or `#rgb`, in either case.
+    pub fn from_hex(hex: &str) -> Result<RGB, ColorError> {
+        let invalid = || ColorError::InvalidHex(hex.to_string());
+        let digits = hex.strip_prefix('#').ok_or_else(invalid)?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+
+        let value = u32::from_str_radix(digits, 16).map_err(|_| invalid())?;
+        match digits.len() {
+            6 => Ok(RGB::from(value)),
+            // Each digit stands for itself twice, #f80 is #ff8800.
+            3 => Ok(RGB {
+                r: ((value >> 8) & 0xF) as u8 * 0x11,
+                g: ((value >> 4) & 0xF) as u8 * 0x11,
+                b: (value & 0xF) as u8 * 0x11,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Hue and saturation of the colour. Its value is dropped, see the module
+/// docs.
+impl From<RGB> for HSV {
+    fn from(rgb: RGB) -> Self {
+        let (hue, chroma, max) = hue_chroma(rgb);
+        let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+        HSV {
+            hue: hue.round() as u16 % 360,
+            saturation: (saturation * 100.0).round() as u8,
+        }
+    }
+}
+
+/// The colour at full value.
+impl TryFrom<HSV> for RGB {
+    type Error = ColorError;
+
+    fn try_from(hsv: HSV) -> Result<Self, Self::Error> {
+        if !hsv.validate() {
+            return Err(ColorError::OutOfRange("hue or saturation"));
+        }
+        let saturation = f64::from(hsv.saturation) / 100.0;
+        Ok(from_hsv(f64::from(hsv.hue), saturation, 1.0))
+    }
+}
+
+impl From<RGB> for Hsl {
+    fn from(rgb: RGB) -> Self {
+        let (hue, chroma, max) = hue_chroma(rgb);
+        let lightness = max - chroma / 2.0;
+        let saturation = if lightness == 0.0 || lightness == 1.0 {
+            0.0
+        } else {
+            (chroma / (1.0 - (2.0 * lightness - 1.0).abs())).min(1.0)
+        };
+        Hsl {
+            hue,
+            saturation: saturation * 100.0,
+            lightness: lightness * 100.0,
+        }
+    }
+}
+
+impl TryFrom<Hsl> for RGB {
+    type Error = ColorError;
+
+    fn try_from(hsl: Hsl) -> Result<Self, Self::Error> {
+        if !(0.0..360.0).contains(&hsl.hue) {
+            return Err(ColorError::OutOfRange("hue"));
+        }
+        if !(0.0..=100.0).contains(&hsl.saturation) {
+            return Err(ColorError::OutOfRange("saturation"));
+        }
+        if !(0.0..=100.0).contains(&hsl.lightness) {
+            return Err(ColorError::OutOfRange("lightness"));
+        }
+
+        let (saturation, lightness) = (hsl.saturation / 100.0, hsl.lightness / 100.0);
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        Ok(from_hue_chroma(hsl.hue, chroma, lightness - chroma / 2.0))
+    }
+}
+
+fn to_linear(value: u8) -> f64 {
+    let value = unit(value);
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn from_linear(value: f64) -> f64 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// CIE XYZ of the colour, with white at Y = 1.
+fn to_xyz(rgb: RGB) -> (f64, f64, f64) {
+    let (r, g, b) = (to_linear(rgb.r), to_linear(rgb.g), to_linear(rgb.b));
+    (
+        0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b,
+        0.212_672_9 * r + 0.715_152_2 * g + 0.072_175_0 * b,
+        0.019_333_9 * r + 0.119_192_0 * g + 0.950_304_1 * b,
+    )
+}
+
+/// Linear sRGB of CIE XYZ, unclipped.
+fn from_xyz(x: f64, y: f64, z: f64) -> (f64, f64, f64) {
+    (
+        3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z,
+        -0.969_266_0 * x + 1.876_010_8 * y + 0.041_556_0 * z,
+        0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z,
+    )
+}
+
+/// Chromaticity of the colour. Black has none and comes out as white's.
+impl From<RGB> for CieXy {
+    fn from(rgb: RGB) -> Self {
+        let (x, y, z) = to_xyz(rgb);
+        let sum = x + y + z;
+        if sum == 0.0 {
+            return D65;
+        }
+        CieXy {
+            x: x / sum,
+            y: y / sum,
+        }
+    }
+}
+
+/// The brightest colour of that chromaticity. Chromaticities outside the
+/// sRGB gamut are clipped to it.
+impl TryFrom<CieXy> for RGB {
+    type Error = ColorError;
+
+    fn try_from(xy: CieXy) -> Result<Self, Self::Error> {
+        if !(0.0..=1.0).contains(&xy.x) {
+            return Err(ColorError::OutOfRange("x"));
+        }
+        if !(xy.y > 0.0 && xy.y <= 1.0 && xy.x + xy.y <= 1.0) {
+            return Err(ColorError::OutOfRange("y"));
+        }
+
+        let (r, g, b) = from_xyz(xy.x / xy.y, 1.0, (1.0 - xy.x - xy.y) / xy.y);
+        let (r, g, b) = (r.max(0.0), g.max(0.0), b.max(0.0));
+        let max = r.max(g).max(b);
+        Ok(RGB {
+            r: channel(from_linear(r / max)),
+            g: channel(from_linear(g / max)),
+            b: channel(from_linear(b / max)),
+        })
+    }
+}
+
+/// XYZ of the D65 white point, the reference white for Lab.
+const WHITE_XYZ: (f64, f64, f64) = (0.950_47, 1.0, 1.088_83);
+
+/// The ratio below which Lab switches from a cube root to a straight line.
+const LAB_DELTA: f64 = 6.0 / 29.0;
+
+fn lab_f(t: f64) -> f64 {
+    if t > LAB_DELTA.powi(3) {
+        t.cbrt()
+    } else {
+        t / (3.0 * LAB_DELTA * LAB_DELTA) + 4.0 / 29.0
+    }
+}
+
+fn lab_f_inverse(t: f64) -> f64 {
+    if t > LAB_DELTA {
+        t.powi(3)
+    } else {
+        3.0 * LAB_DELTA * LAB_DELTA * (t - 4.0 / 29.0)
+    }
+}
+
+impl From<RGB> for Lab {
+    fn from(rgb: RGB) -> Self {
+        let (x, y, z) = to_xyz(rgb);
+        let (fx, fy, fz) = (
+            lab_f(x / WHITE_XYZ.0),
+            lab_f(y / WHITE_XYZ.1),
+            lab_f(z / WHITE_XYZ.2),
+        );
+        Lab {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+}
+
+/// Colours outside the sRGB gamut are clipped to it.
+impl From<Lab> for RGB {
+    fn from(lab: Lab) -> Self {
+        let fy = (lab.l + 16.0) / 116.0;
+        let (r, g, b) = from_xyz(
+            WHITE_XYZ.0 * lab_f_inverse(fy + lab.a / 500.0),
+            WHITE_XYZ.1 * lab_f_inverse(fy),
+            WHITE_XYZ.2 * lab_f_inverse(fy - lab.b / 200.0),
+        );
+        let encode = |linear: f64| channel(from_linear(linear.clamp(0.0, 1.0)));
+        RGB {
+            r: encode(r),
+            g: encode(g),
+            b: encode(b),
+        }
+    }
+}
+
+/// Approximate colour of a black body at that temperature, after Tanner
+/// Helland's fit. Good enough for previews between 1000 and 40000 K.
+impl From<Kelvin> for RGB {
+    fn from(kelvin: Kelvin) -> Self {
+        let t = f64::from(kelvin.0) / 100.0;
+
+        let (r, g) = if t <= 66.0 {
+            (255.0, 99.470_802_586_1 * t.ln() - 161.119_568_166_1)
+        } else {
+            (
+                329.698_727_446 * (t - 60.0).powf(-0.133_204_759_2),
+                288.122_169_528_3 * (t - 60.0).powf(-0.075_514_849_2),
+            )
+        };
+        let b = if t >= 66.0 {
+            255.0
+        } else if t <= 19.0 {
+            0.0
+        } else {
+            138.517_731_223_1 * (t - 10.0).ln() - 305.044_792_730_7
+        };
+        RGB {
+            r: channel(r / 255.0),
+            g: channel(g / 255.0),
+            b: channel(b / 255.0),
+        }
+    }
+}
+
+fn invalid(component: &'static str, value: &str) -> ColorError {
+    ColorError::InvalidComponent {
+        component,
+        value: value.to_string(),
+    }
+}
+
+/// Splits `name(args)` into its name and arguments.
+fn function(color: &str) -> Option<(&str, &str)> {
+    let (name, rest) = color.split_once('(')?;
+    Some((name.trim_end(), rest.strip_suffix(')')?))
+}
+
+/// Components are separated by commas, or by spaces as CSS also allows.
+fn components(args: &str) -> Vec<&str> {
+    if args.contains(',') {
+        args.split(',').map(str::trim).collect()
+    } else {
+        args.split_whitespace().collect()
+    }
+}
+
+fn exactly<'a, const N: usize>(
+    function: &'static str,
+    components: &[&'a str],
+) -> Result<[&'a str; N], ColorError> {
+    <[&str; N]>::try_from(components).map_err(|_| ColorError::ComponentCount {
+        function,
+        found: components.len(),
+    })
+}
+
+/// A number from 0 to `max`, optionally followed by `unit`.
+fn number(component: &'static str, value: &str, unit: &str, max: f64) -> Result<f64, ColorError> {
+    let number: f64 = value
+        .strip_suffix(unit)
+        .unwrap_or(value)
+        .parse()
+        .map_err(|_| invalid(component, value))?;
+    if (0.0..=max).contains(&number) {
+        Ok(number)
+    } else {
+        Err(invalid(component, value))
+    }
+}
+
+fn percentage(component: &'static str, value: &str) -> Result<f64, ColorError> {
+    number(component, value, "%", 100.0)
+}
+
+/// Any angle in degrees, brought into 0 up to 360.
+fn hue(value: &str) -> Result<f64, ColorError> {
+    let degrees: f64 = value
+        .strip_suffix("deg")
+        .unwrap_or(value)
+        .parse()
+        .map_err(|_| invalid("hue", value))?;
+    if !degrees.is_finite() {
+        return Err(invalid("hue", value));
+    }
+    let hue = degrees.rem_euclid(360.0);
+    Ok(if hue < 360.0 { hue } else { 0.0 })
+}
+
+/// 0 to 255, or a percentage of that.
+fn rgb_component(component: &'static str, value: &str) -> Result<u8, ColorError> {
+    if value.ends_with('%') {
+        percentage(component, value).map(|p| channel(p / 100.0))
+    } else {
+        number(component, value, "", 255.0).map(|v| v.round() as u8)
+    }
+}
+
+/// Hue, saturation and value of `hsv(h, s)` or `hsv(h, s, v)`. Value
+/// defaults to 100.
+fn hsv_components(args: &str) -> Result<(f64, f64, f64), ColorError> {
+    let components = components(args);
+    let [h, s, v] = match components.as_slice() {
+        [h, s] => [*h, *s, "100"],
+        _ => exactly("hsv", &components)?,
+    };
+    Ok((
+        hue(h)?,
+        percentage("saturation", s)?,
+        percentage("value", v)?,
+    ))
+}
+
+impl FromStr for RGB {
+    type Err = ColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let color = trimmed.to_ascii_lowercase();
+        if color.is_empty() {
+            return Err(ColorError::Empty);
+        }
+        if color.starts_with('#') {
+            return RGB::from_hex(trimmed);
+        }
+        if color.bytes().all(|b| b.is_ascii_digit()) {
+            return match color.parse::<u32>() {
+                Ok(value) if value <= 0xFF_FFFF => Ok(RGB::from(value)),
+                _ => Err(ColorError::OutOfRange("rgb")),
+            };
+        }
+
+        match function(&color) {
+            Some(("rgb", args)) => {
+                let [r, g, b] = exactly("rgb", &components(args))?;
+                Ok(RGB {
+                    r: rgb_component("red", r)?,
+                    g: rgb_component("green", g)?,
+                    b: rgb_component("blue", b)?,
+                })
+            }
+            Some(("hsl", args)) => {
+                let [h, s, l] = exactly("hsl", &components(args))?;
+                RGB::try_from(Hsl {
+                    hue: hue(h)?,
+                    saturation: percentage("saturation", s)?,
+                    lightness: percentage("lightness", l)?,
+                })
+            }
+            Some(("hsv", args)) => {
+                let (hue, saturation, value) = hsv_components(args)?;
+                Ok(from_hsv(hue, saturation / 100.0, value / 100.0))
+            }
+            Some(_) => Err(ColorError::Unknown(trimmed.to_string())),
+            // Names are looked up without spaces, so "Light Blue" works.
+            None => names::lookup(&color.split_whitespace().collect::<String>())
+                .ok_or_else(|| ColorError::Unknown(trimmed.to_string())),
+        }
+    }
+}
+
+/// `hsv(h, s)` keeps the hue and saturation as written, anything else is
+/// parsed as `RGB` and converted.
+impl FromStr for HSV {
+    type Err = ColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match function(&s.trim().to_ascii_lowercase()) {
+            Some(("hsv", args)) => {
+                let (hue, saturation, _) = hsv_components(args)?;
+                Ok(HSV {
+                    hue: hue.round() as u16 % 360,
+                    saturation: saturation.round() as u8,
+                })
+            }
+            _ => s.parse::<RGB>().map(HSV::from),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::{CieXy, ColorError, Hsl, Kelvin, Lab};
+    use crate::{lightmode::HSV, rgb::RGB};
+
+    const ORANGE: RGB = RGB {
+        r: 255,
+        g: 136,
+        b: 0,
+    };
+
+    fn rgb(r: u8, g: u8, b: u8) -> RGB {
+        RGB { r, g, b }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 0.001
+    }
+
+    #[test]
+    fn hex_test() {
+        assert_eq!(ORANGE.to_hex(), "#ff8800");
+        assert_eq!(RGB::from_hex("#FF8800"), Ok(ORANGE));
+        assert_eq!(RGB::from_hex("#f80"), Ok(ORANGE));
+        for bad in ["ff8800", "#ff880", "#ff88zz", "#+ff880", "#ff88001"].iter() {
+            assert_eq!(
+                RGB::from_hex(bad),
+                Err(ColorError::InvalidHex(bad.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn hsv_test() {
+        let cases = [
+            (rgb(255, 0, 0), 0, 100),
+            (rgb(0, 255, 0), 120, 100),
+            (rgb(0, 0, 255), 240, 100),
+            (rgb(255, 255, 255), 0, 0),
+            (rgb(0, 0, 0), 0, 0),
+            (ORANGE, 32, 100),
+            (rgb(255, 128, 128), 0, 50),
+            (rgb(255, 0, 2), 0, 100),
+        ];
+        for (color, hue, saturation) in cases.iter() {
+            assert_eq!(
+                HSV::from(*color),
+                HSV {
+                    hue: *hue,
+                    saturation: *saturation
+                },
+                "{:?}",
+                color
+            );
+        }
+
+        let pink = HSV {
+            hue: 300,
+            saturation: 50,
+        };
+        assert_eq!(RGB::try_from(pink), Ok(rgb(255, 128, 255)));
+        assert!(RGB::try_from(HSV {
+            hue: 360,
+            saturation: 0
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn hsl_test() {
+        let hsl = Hsl::from(ORANGE);
+        assert!(close(hsl.hue, 32.0));
+        assert!(close(hsl.saturation, 100.0));
+        assert!(close(hsl.lightness, 50.0));
+
+        let teal = Hsl::from(rgb(0, 128, 128));
+        assert!(close(teal.hue, 180.0));
+        assert!(close(teal.lightness, 25.098));
+
+        for r in (0..=255).step_by(51) {
+            for g in (0..=255).step_by(17) {
+                for b in (0..=255).step_by(15) {
+                    let color = rgb(r, g, b);
+                    assert_eq!(RGB::try_from(Hsl::from(color)), Ok(color));
+                }
+            }
+        }
+        assert_eq!(
+            RGB::try_from(Hsl {
+                hue: 0.0,
+                saturation: 120.0,
+                lightness: 50.0
+            }),
+            Err(ColorError::OutOfRange("saturation"))
+        );
+    }
+
+    #[test]
+    fn cie_xy_test() {
+        let references = [
+            (rgb(255, 0, 0), 0.6400, 0.3300),
+            (rgb(0, 255, 0), 0.3000, 0.6000),
+            (rgb(0, 0, 255), 0.1500, 0.0600),
+            (rgb(255, 255, 255), 0.3127, 0.3290),
+        ];
+        for (color, x, y) in references.iter() {
+            let xy = CieXy::from(*color);
+            assert!((xy.x - x).abs() < 0.0005 && (xy.y - y).abs() < 0.0005);
+            assert_eq!(RGB::try_from(xy), Ok(*color));
+        }
+        assert_eq!(RGB::try_from(CieXy::from(ORANGE)), Ok(ORANGE));
+        assert!(RGB::try_from(CieXy { x: 0.5, y: 0.0 }).is_err());
+    }
+
+    #[test]
+    fn lab_test() {
+        let references = [
+            (rgb(255, 0, 0), 53.2408, 80.0925, 67.2032),
+            (rgb(0, 0, 255), 32.2970, 79.1875, -107.8602),
+            (rgb(255, 255, 255), 100.0, 0.0, 0.0),
+            (rgb(0, 0, 0), 0.0, 0.0, 0.0),
+            (rgb(128, 128, 128), 53.5850, 0.0, 0.0),
+        ];
+        for (color, l, a, b) in references.iter() {
+            let lab = Lab::from(*color);
+            assert!(
+                (lab.l - l).abs() < 0.01 && (lab.a - a).abs() < 0.01 && (lab.b - b).abs() < 0.01,
+                "{:?} {:?}",
+                color,
+                lab
+            );
+        }
+
+        for r in (0..=255).step_by(51) {
+            for g in (0..=255).step_by(17) {
+                for b in (0..=255).step_by(15) {
+                    let color = rgb(r, g, b);
+                    assert_eq!(RGB::from(Lab::from(color)), color);
+                }
+            }
+        }
+        // Far outside the gamut, clipped.
+        assert_eq!(
+            RGB::from(Lab {
+                l: 100.0,
+                a: 200.0,
+                b: 0.0
+            })
+            .r,
+            255
+        );
+    }
+
+    #[test]
+    fn kelvin_test() {
+        assert_eq!(RGB::from(Kelvin(6600)), rgb(255, 255, 255));
+        assert_eq!(RGB::from(Kelvin(2700)), rgb(255, 167, 87));
+        assert_eq!(RGB::from(Kelvin(1700)).b, 0);
+        assert_eq!(RGB::from(Kelvin(10000)), rgb(202, 218, 255));
+    }
+
+    #[test]
+    fn parse_test() {
+        let cases = [
+            "#ff8800",
+            "#F80",
+            "  rgb(255, 136, 0) ",
+            "RGB(255 136 0)",
+            "rgb(100%, 53.4%, 0%)",
+            "hsl(32, 100%, 50%)",
+            "hsl(392deg 100 50)",
+            "hsv(32, 100%)",
+            "hsv(32, 100, 100)",
+            "16746496",
+        ];
+        for case in cases.iter() {
+            assert_eq!(case.parse::<RGB>(), Ok(ORANGE), "{}", case);
+        }
+
+        assert_eq!("rebeccapurple".parse(), Ok(rgb(0x66, 0x33, 0x99)));
+        assert_eq!("Light Blue".parse(), Ok(rgb(0xad, 0xd8, 0xe6)));
+        assert_eq!("hsv(0, 0, 50)".parse(), Ok(rgb(128, 128, 128)));
+        assert_eq!("0".parse(), Ok(rgb(0, 0, 0)));
+
+        let hsv = HSV {
+            hue: 359,
+            saturation: 7,
+        };
+        assert_eq!("hsv(359, 7)".parse(), Ok(hsv));
+        assert_eq!(
+            "orange".parse(),
+            Ok(HSV {
+                hue: 39,
+                saturation: 100
+            })
+        );
+    }
+
+    #[test]
+    fn parse_errors_test() {
+        let invalid = |component, value: &str| ColorError::InvalidComponent {
+            component,
+            value: value.to_string(),
+        };
+        assert_eq!("  ".parse::<RGB>(), Err(ColorError::Empty));
+        assert_eq!(
+            "#ff88".parse::<RGB>(),
+            Err(ColorError::InvalidHex("#ff88".to_string()))
+        );
+        assert_eq!(
+            "rgb(255, 300, 0)".parse::<RGB>(),
+            Err(invalid("green", "300"))
+        );
+        assert_eq!("rgb(1,,2)".parse::<RGB>(), Err(invalid("green", "")));
+        assert_eq!(
+            "hsl(32, 100%, 150%)".parse::<RGB>(),
+            Err(invalid("lightness", "150%"))
+        );
+        assert_eq!("hsv(hue, 10)".parse::<HSV>(), Err(invalid("hue", "hue")));
+        assert_eq!(
+            "rgb(1, 2)".parse::<RGB>(),
+            Err(ColorError::ComponentCount {
+                function: "rgb",
+                found: 2
+            })
+        );
+        assert_eq!(
+            "hsv(1, 2, 3, 4)".parse::<HSV>(),
+            Err(ColorError::ComponentCount {
+                function: "hsv",
+                found: 4
+            })
+        );
+        assert_eq!(
+            "16777216".parse::<RGB>(),
+            Err(ColorError::OutOfRange("rgb"))
+        );
+        for unknown in ["bluish", "cmyk(0, 0, 0, 0)", "rgb(1, 2, 3"].iter() {
+            assert_eq!(
+                unknown.parse::<RGB>(),
+                Err(ColorError::Unknown(unknown.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn display_test() {
+        assert_eq!(ORANGE.to_string(), "255, 136, 0");
+        assert_eq!(ORANGE.to_css(), "rgb(255, 136, 0)");
+        assert_eq!(format!("{:#}", ORANGE), "#ff8800");
+
+        let hsv = HSV {
+            hue: 300,
+            saturation: 50,
+        };
+        assert_eq!(hsv.to_string(), "hsv(300, 50)");
+        assert_eq!(format!("{:#}", hsv), "#ff80ff");
+
+        for color in [ORANGE, rgb(1, 2, 3)].iter() {
+            assert_eq!(color.to_css().parse(), Ok(*color));
+            assert_eq!(format!("{:#}", color).parse(), Ok(*color));
+        }
+        assert_eq!(hsv.to_string().parse(), Ok(hsv));
+    }
+}
@@ -1,20 +1,108 @@
 use std::{
+    collections::HashMap,
     convert::TryFrom,
-    fmt::Debug,
-    io::{Error, Read, Write},
-    net::TcpStream,
-    sync::Mutex,
+    error,
+    fmt::{self, Debug},
+    io::{self, Error, ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{self, Receiver},
+        Mutex,
+    },
     time::Duration,
 };
 
-use crate::{bulb::Bulb, lightmode::HSV, rgb::RGB};
-use rand::{prelude::ThreadRng, RngCore};
+use crate::{
+    bulb::Bulb, event::Subscribers, frame::FrameBuffer, lightmode::HSV, method::Method,
+    rate_limit::RateLimiter, reconnect::Reconnector, rgb::RGB,
+};
 use serde::Deserialize;
 
-pub struct BulbConnection<T: Read + Write, R: RngCore> {
+pub struct BulbConnection<T: Read + Write> {
+    /// The bulb as last seen. Starts out as discovered and is kept current
+    /// with the results of successful calls and the bulb's `props`
+    /// notifications, so it reflects changes made by other clients as soon
+    /// as a call or [`poll_events`](Self::poll_events) reads them.
     pub bulb: Bulb,
     pub connection: Mutex<T>,
-    pub rng: R,
+    pub(crate) ids: RequestIds,
+    pub(crate) subscribers: Subscribers,
+    pub(crate) frames: FrameBuffer,
+    pub(crate) reconnector: Option<Reconnector<T>>,
+    pub(crate) response_timeout: Option<Duration>,
+    pub(crate) read_timeout_setter: Option<ReadTimeoutSetter<T>>,
+    pub(crate) limiter: Option<RateLimiter>,
+    pub(crate) music_mode: bool,
+}
+
+/// Applies a read timeout to a stream, so a blocking read can be cut short
+/// when a call's response deadline is about to pass.
+pub(crate) type ReadTimeoutSetter<T> = fn(&T, Option<Duration>) -> io::Result<()>;
+
+impl<T: Read + Write> BulbConnection<T> {
+    pub fn from_connection(bulb: Bulb, connection: T) -> Self {
+        BulbConnection {
+            bulb,
+            connection: Mutex::new(connection),
+            ids: RequestIds::new(),
+            subscribers: Subscribers::default(),
+            frames: FrameBuffer::new(),
+            reconnector: None,
+            response_timeout: None,
+            read_timeout_setter: None,
+            limiter: None,
+            music_mode: false,
+        }
+    }
+
+    /// Limits how long a call waits for its reply. A call that runs past it
+    /// fails with [`MethodCallError::Timeout`].
+    ///
+    /// The deadline is checked between reads, so a stream whose reads block
+    /// on their own should have a read timeout as well. [`TcpConnection`]
+    /// takes care of that.
+    pub fn with_response_timeout(mut self, timeout: Duration) -> Self {
+        self.response_timeout = Some(timeout);
+        self
+    }
+
+    /// Returns a receiver for the notifications the bulb pushes whenever its
+    /// state changes. Notifications arrive on the same socket as replies, so
+    /// they are only picked up while a method call or
+    /// [`poll_events`](Self::poll_events) is reading from it.
+    pub fn notifications(&mut self) -> Receiver<Notification> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers
+            .listen_notifications(move |n| sender.send(n.clone()).is_ok());
+        receiver
+    }
+
+    /// Whether music mode was last turned on through this connection.
+    pub fn music_mode(&self) -> bool {
+        self.music_mode
+    }
+}
+
+/// Source of request ids. Counts up from 1 and wraps within the positive
+/// `i16` range, so an id only comes around again after 32767 requests.
+#[derive(Debug)]
+pub(crate) struct RequestIds {
+    last: i16,
+}
+
+impl RequestIds {
+    pub(crate) fn new() -> Self {
+        RequestIds { last: 0 }
+    }
+
+    pub(crate) fn next_id(&mut self) -> i16 {
+        self.last = if self.last == i16::MAX {
+            1
+        } else {
+            self.last + 1
+        };
+        self.last
+    }
 }
 
 #[derive(Debug)]
@@ -22,9 +110,95 @@ pub enum MethodCallError {
     BadRequest,
     UnsupportedMethod,
     IOError(std::io::Error),
+    /// The reply didn't arrive before the response deadline, or the request
+    /// couldn't be written within the write timeout.
+    Timeout,
+    /// The command quota is used up and the rate limit says to fail fast.
+    RateLimited,
+    /// The command quota is used up, so the change was held back to be sent
+    /// later. See [`RateLimitPolicy::Coalesce`](crate::rate_limit::RateLimitPolicy::Coalesce).
+    Deferred,
     ParseError,
     SynchronizationError,
     ErrorResponse(ErrorResponse),
+    /// One of the errors above, with the call it came from. Errors returned
+    /// by method calls always come wrapped like this, [`kind`](Self::kind)
+    /// gets to the error itself.
+    Call {
+        method: Method,
+        bulb_id: String,
+        source: Box<MethodCallError>,
+    },
+}
+
+impl MethodCallError {
+    /// The error without the context of the call it came from.
+    pub fn kind(&self) -> &MethodCallError {
+        match self {
+            MethodCallError::Call { source, .. } => source.kind(),
+            err => err,
+        }
+    }
+
+    /// The method that was called, if known.
+    pub fn method(&self) -> Option<Method> {
+        match self {
+            MethodCallError::Call { method, .. } => Some(*method),
+            _ => None,
+        }
+    }
+
+    /// Id of the bulb the call was made to, if known.
+    pub fn bulb_id(&self) -> Option<&str> {
+        match self {
+            MethodCallError::Call { bulb_id, .. } => Some(bulb_id),
+            _ => None,
+        }
+    }
+
+    /// Attaches the call the error came from, unless it already has one.
+    pub(crate) fn in_call(self, method: Method, bulb: &Bulb) -> MethodCallError {
+        match self {
+            err @ MethodCallError::Call { .. } => err,
+            err => MethodCallError::Call {
+                method,
+                bulb_id: bulb.id.clone(),
+                source: Box::new(err),
+            },
+        }
+    }
+}
+
+impl fmt::Display for MethodCallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MethodCallError::BadRequest => write!(f, "invalid arguments for the method"),
+            MethodCallError::UnsupportedMethod => write!(f, "method not supported by the bulb"),
+            MethodCallError::IOError(err) => write!(f, "I/O error: {}", err),
+            MethodCallError::Timeout => write!(f, "timed out"),
+            MethodCallError::RateLimited => write!(f, "command quota used up"),
+            MethodCallError::Deferred => write!(f, "deferred until the command quota allows"),
+            MethodCallError::ParseError => write!(f, "couldn't parse the bulb's reply"),
+            MethodCallError::SynchronizationError => {
+                write!(f, "reply didn't belong to the request")
+            }
+            MethodCallError::ErrorResponse(err) => write!(f, "bulb replied with {}", err),
+            MethodCallError::Call {
+                method,
+                bulb_id,
+                source,
+            } => write!(f, "{} on bulb {}: {}", method, bulb_id, source),
+        }
+    }
+}
+
+impl error::Error for MethodCallError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            MethodCallError::IOError(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
 pub trait MethodCallResponse<'a>: Deserialize<'a> + Debug {
@@ -37,12 +211,135 @@ pub struct ErrorResponse {
     error: BulbErrorResponse,
 }
 
+impl ErrorResponse {
+    /// Id of the request that failed.
+    pub fn id(&self) -> i16 {
+        self.id
+    }
+
+    pub fn error(&self) -> &BulbErrorResponse {
+        &self.error
+    }
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl error::Error for ErrorResponse {}
+
 #[derive(Debug, Deserialize)]
 pub struct BulbErrorResponse {
     code: i32,
     message: String,
 }
 
+impl BulbErrorResponse {
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn kind(&self) -> BulbErrorKind {
+        BulbErrorKind::classify(self.code, &self.message)
+    }
+}
+
+impl fmt::Display for BulbErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error {}: {}", self.code, self.message)
+    }
+}
+
+/// Known errors a bulb replies with. Most of them share code -1, so they
+/// are told apart by their message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulbErrorKind {
+    /// The method isn't supported, or not in the bulb's current state.
+    MethodNotSupported,
+    /// The bulb rejected the parameters.
+    InvalidParams,
+    /// The bulb's command quota is used up. It drops the connection soon
+    /// after replying with this.
+    ClientQuotaExceeded,
+    /// The request couldn't be parsed.
+    InvalidCommand,
+    /// The catch-all firmware error, code -5000.
+    GeneralError,
+    Unknown,
+}
+
+impl BulbErrorKind {
+    fn classify(code: i32, message: &str) -> BulbErrorKind {
+        let message = message.to_lowercase();
+        if message.contains("quota") {
+            BulbErrorKind::ClientQuotaExceeded
+        } else if message.contains("not supported") || message.contains("unsupported") {
+            BulbErrorKind::MethodNotSupported
+        } else if message.contains("param") {
+            BulbErrorKind::InvalidParams
+        } else if message.contains("invalid command") {
+            BulbErrorKind::InvalidCommand
+        } else if code == -5000 || message.contains("general error") {
+            BulbErrorKind::GeneralError
+        } else {
+            BulbErrorKind::Unknown
+        }
+    }
+}
+
+/// An unsolicited message from the bulb, such as
+/// `{"method":"props","params":{"power":"on"}}`.
+/// Param values are kept as strings, the same way discovery reports them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub method: String,
+    pub params: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct NotificationFrame {
+    method: String,
+    params: serde_json::Map<String, serde_json::Value>,
+}
+
+impl From<NotificationFrame> for Notification {
+    fn from(frame: NotificationFrame) -> Self {
+        Notification {
+            method: frame.method,
+            params: frame
+                .params
+                .into_iter()
+                .map(|(key, value)| match value {
+                    serde_json::Value::String(s) => (key, s),
+                    other => (key, other.to_string()),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Notification {
+    /// Applies the state a `props` notification reports to `bulb`. Other
+    /// notifications leave it alone.
+    pub fn apply_to(&self, bulb: &mut Bulb) {
+        if self.method == "props" {
+            bulb.apply_props(&self.params);
+        }
+    }
+
+    pub(crate) fn parse(frame: &str) -> Option<Notification> {
+        serde_json::from_str::<NotificationFrame>(frame)
+            .ok()
+            .map(Notification::from)
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct StringVecResponse {
     pub id: i16,
@@ -89,16 +386,101 @@ impl<'a> MethodCallResponse<'a> for StringVecResponse {
     }
 }
 
-pub type TcpConnection = BulbConnection<TcpStream, ThreadRng>;
+pub type TcpConnection = BulbConnection<TcpStream>;
 
 impl TcpConnection {
     pub fn new(bulb: Bulb) -> Result<Self, Error> {
-        return TcpStream::connect(&bulb.ip_address).map(|connection| BulbConnection {
-            bulb: bulb,
-            connection: Mutex::new(connection),
-            rng: rand::thread_rng(),
-        });
+        TcpConnection::with_options(bulb, &ConnectionOptions::new())
+    }
+
+    pub fn with_options(bulb: Bulb, options: &ConnectionOptions) -> Result<Self, Error> {
+        let stream = options.connect(&bulb.ip_address)?;
+        let mut connection = BulbConnection::from_connection(bulb, stream);
+        connection.response_timeout = options.response_timeout;
+        connection.read_timeout_setter = Some(TcpStream::set_read_timeout);
+        Ok(connection)
+    }
+}
+
+/// Timeouts for a [`TcpConnection`]. Everything is unset by default, which
+/// means waiting as long as the OS does.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use libyee::connection::ConnectionOptions;
+/// let options = ConnectionOptions::new()
+///     .connect_timeout(Duration::from_secs(2))
+///     .response_timeout(Duration::from_secs(1))
+///     .write_timeout(Duration::from_millis(500));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionOptions {
+    connect_timeout: Option<Duration>,
+    response_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl ConnectionOptions {
+    pub fn new() -> Self {
+        ConnectionOptions::default()
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// How long a single call waits for its reply, across every read it
+    /// takes. See [`BulbConnection::with_response_timeout`].
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.response_timeout = Some(timeout);
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    pub fn get_connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    pub fn get_response_timeout(&self) -> Option<Duration> {
+        self.response_timeout
+    }
+
+    pub fn get_write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
     }
+
+    /// Opens a stream to `address` honouring the connect and write timeouts.
+    pub(crate) fn connect(&self, address: &str) -> io::Result<TcpStream> {
+        let stream = match self.connect_timeout {
+            Some(timeout) => {
+                let mut last_error = io::Error::from(ErrorKind::AddrNotAvailable);
+                let mut connected = None;
+                for addr in address.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&addr, timeout) {
+                        Ok(stream) => {
+                            connected = Some(stream);
+                            break;
+                        }
+                        Err(err) => last_error = err,
+                    }
+                }
+                connected.ok_or(last_error)?
+            }
+            None => TcpStream::connect(address)?,
+        };
+        stream.set_write_timeout(self.write_timeout)?;
+        Ok(stream)
+    }
+}
+
+/// Whether a failed read or write ran into a timeout set on the stream.
+pub(crate) fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
 }
 
 pub enum MusicMode<'a> {
@@ -138,18 +520,27 @@ impl From<&AdjustAction> for &str {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum CronType {
     PowerOff,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum CfAction {
     Recover,
     Stay,
     TurnOff,
 }
 
+/// Serializes with the `serde` feature. It borrows its colours and flow, so
+/// scenes are read back as a [`SceneBuf`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Scene<'a, 'b> {
     Color(&'a RGB, Brightness),
+    #[cfg_attr(feature = "serde", serde(rename = "hsv"))]
     HSV(&'a HSV, Brightness),
     Ct(Ct, Brightness),
     Cf(&'b ColorFlow),
@@ -158,24 +549,61 @@ pub enum Scene<'a, 'b> {
     AutoDelayOff(Brightness, u16),
 }
 
+/// A [`Scene`] that owns its colours and flow, for keeping scenes around
+/// such as in JSON with the `serde` feature. It has the same form as a
+/// serialized `Scene`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SceneBuf {
+    Color(RGB, Brightness),
+    #[cfg_attr(feature = "serde", serde(rename = "hsv"))]
+    HSV(HSV, Brightness),
+    Ct(Ct, Brightness),
+    Cf(ColorFlow),
+
+    /// brightness, minutes
+    AutoDelayOff(Brightness, u16),
+}
+
+impl SceneBuf {
+    /// The scene to pass to `set_scene`.
+    pub fn as_scene(&self) -> Scene<'_, '_> {
+        match self {
+            SceneBuf::Color(rgb, brightness) => Scene::Color(rgb, *brightness),
+            SceneBuf::HSV(hsv, brightness) => Scene::HSV(hsv, *brightness),
+            SceneBuf::Ct(ct, brightness) => Scene::Ct(*ct, *brightness),
+            SceneBuf::Cf(cf) => Scene::Cf(cf),
+            SceneBuf::AutoDelayOff(brightness, minutes) => {
+                Scene::AutoDelayOff(*brightness, *minutes)
+            }
+        }
+    }
+}
+
 pub const MIN_AUTO_DELAY_OFF_MINUTES: u8 = 1;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ColorFlow {
     pub count: u16,
     pub action: CfAction,
     pub sequence: Vec<FlowTuple>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cron {
     pub cron_type: CronType,
     pub minutes: u16,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FlowTuple {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_impls::millis"))]
     pub duration: Duration,
+    #[cfg_attr(feature = "serde", serde(flatten))]
     pub mode: FlowTupleMode,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ColorFlowTupleMode {
     pub color: RGB,
     pub brightness: Brightness,
@@ -184,11 +612,14 @@ pub struct ColorFlowTupleMode {
 pub type Ct = u16;
 pub type Brightness = u8;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CtFlowTupleMode {
     pub ct: Ct,
     pub brightness: Brightness,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "lowercase"))]
 pub enum FlowTupleMode {
     Color(ColorFlowTupleMode),
     Ct(CtFlowTupleMode),
@@ -197,6 +628,7 @@ pub enum FlowTupleMode {
 
 pub const MINIMUM_CF_DURATION: Duration = Duration::from_millis(50);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PowerMode {
     Ct = 1,
     Rgb = 2,
@@ -207,10 +639,15 @@ pub enum PowerMode {
 
 pub const MAX_BRIGHTNESS: u8 = 100;
 pub const MINIMUM_TRANSITION_DURATION: Duration = Duration::from_millis(30);
+/// The widest colour temperature range of any model. Each model's own is in
+/// [`Capabilities`](crate::model::Capabilities).
 pub const CT_MIN: u16 = 1700;
 pub const CT_MAX: u16 = 6500;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum TransitionMode {
     Sudden,
-    Smooth(Duration),
+    Smooth(#[cfg_attr(feature = "serde", serde(with = "crate::serde_impls::millis"))] Duration),
 }
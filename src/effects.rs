@@ -0,0 +1,258 @@
+//! Ready-made color flows, modelled on the transitions shipped with the
+//! Yeelight app. Durations are in milliseconds. Effects that take
+//! parameters return [`FlowError`] for values a flow can't be built from,
+//! such as a step shorter than the bulb allows.
+
+use crate::{
+    connection::{CfAction, ColorFlow},
+    flow::FlowError,
+    rgb::RGB,
+};
+
+const RED: RGB = RGB { r: 255, g: 0, b: 0 };
+const GREEN: RGB = RGB { r: 0, g: 255, b: 0 };
+const BLUE: RGB = RGB { r: 0, g: 0, b: 255 };
+const WHITE: RGB = RGB {
+    r: 255,
+    g: 255,
+    b: 255,
+};
+
+/// Candle light: warm white wavering in brightness, until stopped.
+pub fn candle_flicker() -> ColorFlow {
+    [
+        (50, 800),
+        (30, 800),
+        (80, 1200),
+        (60, 800),
+        (90, 1200),
+        (50, 2400),
+        (80, 1200),
+        (60, 800),
+        (90, 400),
+        (70, 400),
+    ]
+    .iter()
+    .fold(ColorFlow::builder(), |flow, (bright, millis)| {
+        flow.ct(2700, *bright, *millis)
+    })
+    .forever()
+    .build()
+    .expect("candle flicker steps are valid")
+}
+
+/// Red and blue taking turns, until stopped.
+pub fn police(millis: u64, brightness: u8) -> Result<ColorFlow, FlowError> {
+    ColorFlow::builder()
+        .color(RED, brightness, millis)
+        .color(BLUE, brightness, millis)
+        .forever()
+        .build()
+}
+
+/// Flashes through red, green, cyan and violet in time with `bpm` beats a
+/// minute, until stopped.
+pub fn disco(bpm: u16) -> Result<ColorFlow, FlowError> {
+    let millis = 60_000 / u64::from(bpm.max(1));
+    [
+        RED,
+        RGB {
+            r: 128,
+            g: 255,
+            b: 0,
+        },
+        RGB {
+            r: 0,
+            g: 255,
+            b: 255,
+        },
+        RGB {
+            r: 128,
+            g: 0,
+            b: 255,
+        },
+    ]
+    .iter()
+    .fold(ColorFlow::builder(), |flow, color| {
+        flow.color(*color, 100, millis).color(*color, 1, millis)
+    })
+    .forever()
+    .build()
+}
+
+/// Wakes up from a dim red glow through orange to bright daylight over
+/// `millis`, then stays on.
+pub fn sunrise(millis: u64) -> Result<ColorFlow, FlowError> {
+    ColorFlow::builder()
+        .color(
+            RGB {
+                r: 255,
+                g: 77,
+                b: 0,
+            },
+            1,
+            50,
+        )
+        .color(
+            RGB {
+                r: 255,
+                g: 155,
+                b: 0,
+            },
+            10,
+            millis / 2,
+        )
+        .ct(5000, 100, millis / 2)
+        .then(CfAction::Stay)
+        .build()
+}
+
+/// Dims from warm white through orange to a red glow over `millis`, then
+/// turns off.
+pub fn sunset(millis: u64) -> Result<ColorFlow, FlowError> {
+    ColorFlow::builder()
+        .ct(2700, 10, 50)
+        .color(
+            RGB {
+                r: 255,
+                g: 155,
+                b: 0,
+            },
+            5,
+            millis / 2,
+        )
+        .color(
+            RGB {
+                r: 255,
+                g: 77,
+                b: 0,
+            },
+            1,
+            millis / 2,
+        )
+        .then(CfAction::TurnOff)
+        .build()
+}
+
+/// Pulses `color` up to `brightness` and back down, `times` times.
+pub fn pulse(color: RGB, brightness: u8, millis: u64, times: u16) -> Result<ColorFlow, FlowError> {
+    ColorFlow::builder()
+        .color(color, brightness, millis)
+        .color(color, 1, millis)
+        .repeat(times)
+        .build()
+}
+
+/// Flashes white as fast as the bulb can, until stopped.
+pub fn strobe() -> ColorFlow {
+    ColorFlow::builder()
+        .color(WHITE, 100, 50)
+        .color(WHITE, 1, 50)
+        .forever()
+        .build()
+        .expect("strobe steps are valid")
+}
+
+/// Red throbbing between full and 60% brightness, until stopped.
+pub fn alarm(millis: u64) -> Result<ColorFlow, FlowError> {
+    ColorFlow::builder()
+        .color(RED, 100, millis)
+        .color(RED, 60, millis)
+        .forever()
+        .build()
+}
+
+/// Red and green, each held for `sleep_millis`, until stopped.
+pub fn christmas(millis: u64, brightness: u8, sleep_millis: u64) -> Result<ColorFlow, FlowError> {
+    ColorFlow::builder()
+        .color(RED, brightness, millis)
+        .sleep(sleep_millis)
+        .color(GREEN, brightness, millis)
+        .sleep(sleep_millis)
+        .forever()
+        .build()
+}
+
+/// Slowly drifts through saturated orange, lime, aqua, violet and pink,
+/// until stopped.
+pub fn lsd(millis: u64, brightness: u8) -> Result<ColorFlow, FlowError> {
+    [
+        RGB {
+            r: 255,
+            g: 13,
+            b: 0,
+        },
+        RGB {
+            r: 179,
+            g: 255,
+            b: 0,
+        },
+        RGB {
+            r: 0,
+            g: 255,
+            b: 221,
+        },
+        RGB {
+            r: 183,
+            g: 0,
+            b: 255,
+        },
+        RGB {
+            r: 255,
+            g: 0,
+            b: 153,
+        },
+    ]
+    .iter()
+    .fold(ColorFlow::builder(), |flow, color| {
+        flow.color(*color, brightness, millis)
+    })
+    .forever()
+    .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        alarm, candle_flicker, christmas, disco, lsd, police, pulse, strobe, sunrise, sunset,
+    };
+    use crate::{flow::FlowError, model::BACKGROUND, rgb::RGB};
+
+    #[test]
+    fn stock_effects_test() {
+        let flows = vec![
+            candle_flicker(),
+            police(300, 100).unwrap(),
+            disco(120).unwrap(),
+            sunrise(60_000).unwrap(),
+            sunset(60_000).unwrap(),
+            pulse(RGB { r: 0, g: 0, b: 255 }, 100, 250, 3).unwrap(),
+            strobe(),
+            alarm(250).unwrap(),
+            christmas(250, 100, 3000).unwrap(),
+            lsd(3000, 100).unwrap(),
+        ];
+        for flow in &flows {
+            assert!(flow.params(&BACKGROUND).is_ok());
+        }
+
+        assert_eq!(flows[5].count, 6);
+        assert_eq!(flows[3].count, 3);
+    }
+
+    #[test]
+    fn bad_parameters_test() {
+        assert_eq!(
+            disco(2000).err(),
+            Some(FlowError::DurationTooShort { step: 0 })
+        );
+        assert_eq!(
+            sunrise(60).err(),
+            Some(FlowError::DurationTooShort { step: 1 })
+        );
+        assert_eq!(
+            police(300, 120).err(),
+            Some(FlowError::BrightnessOutOfRange { step: 0 })
+        );
+    }
+}
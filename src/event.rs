@@ -0,0 +1,297 @@
+use std::{
+    convert::TryFrom,
+    io::{Read, Write},
+    sync::mpsc::{self, Receiver},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bulb::Bulb,
+    connection::{BulbConnection, MethodCallError, Notification},
+    lightmode::LightMode,
+    method_calls::{next_reply, read_frames},
+    power::Power,
+};
+
+/// A change to a bulb's state, or to the connection to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BulbEvent {
+    Power(Power),
+    /// The new light mode, with colour values the notification left out
+    /// filled in from the last known state.
+    Mode(LightMode),
+    /// Percentage of maximum brightness, between 1 and 100.
+    Brightness(u8),
+    /// Whether a color flow is running.
+    Flowing(bool),
+    /// The stream broke. Only reported by reconnecting connections.
+    ConnectionLost,
+    /// A reconnect replaced the broken stream.
+    ConnectionRestored,
+}
+
+impl BulbEvent {
+    /// Turns a `props` notification into events, in the order power, mode,
+    /// brightness, flowing. `bulb` is expected to have the notification
+    /// applied already and `previous` is its light mode from before, so a
+    /// mode event only comes out when the mode actually changed, complete
+    /// even when reported by colour values alone. Background light
+    /// properties aren't reported.
+    pub fn from_notification(
+        notification: &Notification,
+        previous: &LightMode,
+        bulb: &Bulb,
+    ) -> Vec<BulbEvent> {
+        let mut events = Vec::new();
+        if notification.method != "props" {
+            return events;
+        }
+        let params = &notification.params;
+
+        if let Some(power) = params.get("power").and_then(|p| Power::try_from(p).ok()) {
+            events.push(BulbEvent::Power(power));
+        }
+        if bulb.color_mode != *previous {
+            events.push(BulbEvent::Mode(bulb.color_mode.clone()));
+        }
+        if let Some(bright) = params.get("bright").and_then(|b| b.parse().ok()) {
+            events.push(BulbEvent::Brightness(bright));
+        }
+        match params.get("flowing").map(String::as_str) {
+            Some("1") => events.push(BulbEvent::Flowing(true)),
+            Some("0") => events.push(BulbEvent::Flowing(false)),
+            _ => {}
+        }
+        events
+    }
+}
+
+/// Takes what is delivered, and says whether it wants more.
+type Listener<T> = Box<dyn FnMut(&T) -> bool + Send>;
+
+/// Everyone listening to a connection, for notifications as the bulb sends
+/// them and for the events made of them. Shared by the blocking and async
+/// connections, which only differ in the channels they hand out.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    notifications: Vec<Listener<Notification>>,
+    events: Vec<Listener<BulbEvent>>,
+}
+
+impl Subscribers {
+    /// Adds a notification listener, kept until it returns `false`.
+    pub(crate) fn listen_notifications(
+        &mut self,
+        listener: impl FnMut(&Notification) -> bool + Send + 'static,
+    ) {
+        self.notifications.push(Box::new(listener));
+    }
+
+    /// Adds an event listener, kept until it returns `false`.
+    pub(crate) fn listen_events(
+        &mut self,
+        listener: impl FnMut(&BulbEvent) -> bool + Send + 'static,
+    ) {
+        self.events.push(Box::new(listener));
+    }
+
+    /// Keeps `bulb` current with a notification, then hands it and the
+    /// events it makes to every live listener, forgetting the ones whose
+    /// receiver was dropped.
+    pub(crate) fn deliver(&mut self, bulb: &mut Bulb, notification: Notification) {
+        let previous = bulb.color_mode.clone();
+        notification.apply_to(bulb);
+        for event in BulbEvent::from_notification(&notification, &previous, bulb) {
+            self.emit(event);
+        }
+        self.notifications
+            .retain_mut(|listener| listener(&notification));
+    }
+
+    pub(crate) fn emit(&mut self, event: BulbEvent) {
+        self.events.retain_mut(|listener| listener(&event));
+    }
+}
+
+impl<T: Read + Write> BulbConnection<T> {
+    /// Returns a receiver for typed state changes. Like
+    /// [`notifications`](Self::notifications), these are picked up while a
+    /// method call or [`poll_events`](Self::poll_events) is reading from the
+    /// socket.
+    pub fn events(&mut self) -> Receiver<BulbEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers
+            .listen_events(move |event| sender.send(event.clone()).is_ok());
+        receiver
+    }
+
+    /// Calls `callback` with every event, on the thread making the call that
+    /// picked the event up.
+    pub fn on_event(&mut self, callback: impl FnMut(&BulbEvent) + Send + 'static) {
+        let mut callback = callback;
+        self.subscribers.listen_events(move |event| {
+            callback(event);
+            true
+        });
+    }
+
+    /// Reads from the socket without making a call, for up to `timeout`,
+    /// and hands what the bulb pushes to the listeners. Returns the number
+    /// of notifications delivered as soon as there are any, or 0 when the
+    /// time runs out first. Call it in a loop to follow a bulb between calls.
+    ///
+    /// The deadline is checked between reads, as with
+    /// [`with_response_timeout`](Self::with_response_timeout). On a
+    /// reconnecting connection a broken stream is replaced and the IO error
+    /// returned.
+    pub fn poll_events(&mut self, timeout: Duration) -> Result<usize, MethodCallError> {
+        if self.is_broken() {
+            self.reconnect()?;
+        }
+
+        let result = self.read_notifications(Instant::now() + timeout);
+        if let Err(MethodCallError::IOError(_)) = result {
+            if self.can_reconnect() {
+                let _ = self.reconnect();
+            }
+        }
+        result
+    }
+
+    fn read_notifications(&mut self, deadline: Instant) -> Result<usize, MethodCallError> {
+        let mut conn = self
+            .connection
+            .lock()
+            .map_err(|_| MethodCallError::SynchronizationError)?;
+
+        let mut delivered = 0;
+        let result = loop {
+            let (bulb, subscribers) = (&mut self.bulb, &mut self.subscribers);
+            // A reply here belongs to a call that gave up on it, nobody is
+            // waiting for it any more.
+            let reply = next_reply(&mut self.frames, |n| {
+                subscribers.deliver(bulb, n);
+                delivered += 1;
+            });
+            match reply {
+                Ok(Some(_)) => continue,
+                Ok(None) if delivered > 0 => break Ok(delivered),
+                Ok(None) => {}
+                Err(err) => break Err(err),
+            }
+
+            match read_frames(
+                &mut *conn,
+                &mut self.frames,
+                Some(deadline),
+                self.read_timeout_setter,
+            ) {
+                Ok(()) => {}
+                Err(MethodCallError::Timeout) => break Ok(0),
+                Err(err) => break Err(err),
+            }
+        };
+
+        // Calls without a response timeout expect reads to block.
+        if let Some(set_read_timeout) = self.read_timeout_setter {
+            let _ = set_read_timeout(&conn, None);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        net::TcpListener,
+        sync::{mpsc, Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use super::BulbEvent;
+    use crate::{
+        connection::{BulbConnection, TcpConnection},
+        lightmode::LightMode,
+        method::Method,
+        power::Power,
+        rgb::RGB,
+        test_utils::{bulb_with_methods, ScriptedConnection},
+    };
+
+    #[test]
+    fn props_events_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"method\":\"props\",\"params\":{\"power\":\"on\",\"bright\":\"40\",\"flowing\":1}}\r\n",
+            "{\"method\":\"props\",\"params\":{\"rgb\":16711680,\"color_mode\":1}}\r\n",
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+        ]);
+        let mut conn = BulbConnection::from_connection(bulb_with_methods(&[Method::Toggle]), mock);
+        let events = conn.events();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        conn.on_event(move |event| log.lock().unwrap().push(event.clone()));
+
+        conn.toggle().unwrap();
+
+        let expected = vec![
+            BulbEvent::Power(Power::On),
+            BulbEvent::Brightness(40),
+            BulbEvent::Flowing(true),
+            BulbEvent::Mode(LightMode::Color(RGB { r: 255, g: 0, b: 0 })),
+        ];
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), expected);
+        assert_eq!(*seen.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn unchanged_mode_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"method\":\"props\",\"params\":{\"rgb\":16711680}}\r\n",
+            "{\"method\":\"props\",\"params\":{\"ct\":4000,\"color_mode\":2}}\r\n",
+            "{\"method\":\"props\",\"params\":{\"ct\":3000}}\r\n",
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+        ]);
+        let mut bulb = bulb_with_methods(&[Method::Toggle]);
+        bulb.color_mode = LightMode::ColorTemperature(4000);
+        let mut conn = BulbConnection::from_connection(bulb, mock);
+        let events = conn.events();
+
+        conn.toggle().unwrap();
+
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![BulbEvent::Mode(LightMode::ColorTemperature(3000))]
+        );
+    }
+
+    #[test]
+    fn poll_events_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut bulb = bulb_with_methods(&[Method::Toggle]);
+        bulb.ip_address = listener.local_addr().unwrap().to_string();
+
+        let (polled, wait_for_poll) = mpsc::channel::<()>();
+        let bulb_side = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write_all(b"{\"method\":\"props\",\"params\":{\"power\":\"on\"}}\r\n")
+                .unwrap();
+            let _ = wait_for_poll.recv();
+        });
+
+        let mut conn = TcpConnection::new(bulb).unwrap();
+        let events = conn.events();
+
+        assert_eq!(conn.poll_events(Duration::from_secs(5)).unwrap(), 1);
+        assert_eq!(events.try_recv().unwrap(), BulbEvent::Power(Power::On));
+        assert_eq!(conn.bulb.power, Power::On);
+
+        assert_eq!(conn.poll_events(Duration::from_millis(50)).unwrap(), 0);
+        assert!(events.try_recv().is_err());
+
+        polled.send(()).unwrap();
+        bulb_side.join().unwrap();
+    }
+}
@@ -0,0 +1,451 @@
+use std::{convert::TryFrom, error, fmt, str::FromStr, time::Duration};
+
+use crate::{
+    connection::{
+        CfAction, ColorFlow, ColorFlowTupleMode, CtFlowTupleMode, FlowTuple, FlowTupleMode,
+        MethodCallError, CT_MAX, CT_MIN, MAX_BRIGHTNESS, MINIMUM_CF_DURATION,
+    },
+    rgb::RGB,
+};
+
+/// Why a color flow can't be built. Steps are counted from 0 in the order
+/// they were added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlowError {
+    /// The flow has no steps.
+    Empty,
+    /// The step is shorter than [`MINIMUM_CF_DURATION`].
+    DurationTooShort { step: usize },
+    /// The step's brightness is above [`MAX_BRIGHTNESS`].
+    BrightnessOutOfRange { step: usize },
+    /// The step's colour temperature is outside [`CT_MIN`] to [`CT_MAX`].
+    /// The narrower range of the light the flow is sent to is checked then,
+    /// see [`Bulb::capabilities`](crate::bulb::Bulb::capabilities).
+    CtOutOfRange { step: usize },
+    /// The repeats make more state changes than the bulb can count.
+    CountTooLarge,
+    /// A gradient stop, counted from 0, with a colour, colour temperature
+    /// or brightness out of range.
+    InvalidStop { stop: usize },
+    /// A flow expression whose values don't make up whole tuples of four.
+    WrongLength { values: usize },
+    /// A value in a flow expression, counted from 0, that isn't a whole
+    /// number in range.
+    InvalidValue { position: usize, value: String },
+    /// The step's mode isn't 1 (colour), 2 (colour temperature) or 7 (sleep).
+    UnknownMode { step: usize, mode: u32 },
+    /// The step's colour is above `0xFFFFFF`.
+    ColorOutOfRange { step: usize },
+    /// The action isn't 0 (recover), 1 (stay) or 2 (turn off).
+    UnknownAction { action: u32 },
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlowError::Empty => write!(f, "color flow has no steps"),
+            FlowError::DurationTooShort { step } => write!(
+                f,
+                "step {} is shorter than {} ms",
+                step,
+                MINIMUM_CF_DURATION.as_millis()
+            ),
+            FlowError::BrightnessOutOfRange { step } => {
+                write!(f, "step {} has a brightness above {}", step, MAX_BRIGHTNESS)
+            }
+            FlowError::CtOutOfRange { step } => write!(
+                f,
+                "step {} has a colour temperature outside {}-{} K",
+                step, CT_MIN, CT_MAX
+            ),
+            FlowError::CountTooLarge => write!(f, "color flow repeats too often"),
+            FlowError::InvalidStop { stop } => write!(f, "gradient stop {} is out of range", stop),
+            FlowError::WrongLength { values } => write!(
+                f,
+                "expected tuples of 4 values, optionally after count and action, got {} values",
+                values
+            ),
+            FlowError::InvalidValue { position, value } => {
+                write!(f, "value {} ({:?}) is not a valid number", position, value)
+            }
+            FlowError::UnknownMode { step, mode } => {
+                write!(f, "step {} has unknown mode {}", step, mode)
+            }
+            FlowError::ColorOutOfRange { step } => {
+                write!(f, "step {} has a colour above 0xFFFFFF", step)
+            }
+            FlowError::UnknownAction { action } => write!(f, "unknown flow action {}", action),
+        }
+    }
+}
+
+impl error::Error for FlowError {}
+
+impl From<FlowError> for MethodCallError {
+    fn from(_: FlowError) -> Self {
+        MethodCallError::BadRequest
+    }
+}
+
+/// Builds a [`ColorFlow`] step by step, checking each step as it is added:
+///
+/// ```
+/// use libyee::{connection::CfAction, rgb::RGB};
+/// use libyee::connection::ColorFlow;
+///
+/// let flow = ColorFlow::builder()
+///     .color(RGB { r: 255, g: 0, b: 0 }, 100, 500)
+///     .ct(2700, 50, 500)
+///     .sleep(1000)
+///     .repeat(3)
+///     .then(CfAction::Recover)
+///     .build()
+///     .unwrap();
+/// assert_eq!(flow.count, 9);
+/// ```
+///
+/// The first invalid step is reported by [`build`](Self::build). By default
+/// the flow runs once and the bulb then goes back to how it was before.
+pub struct ColorFlowBuilder {
+    repeat: u16,
+    action: CfAction,
+    sequence: Vec<FlowTuple>,
+    error: Option<FlowError>,
+}
+
+impl Default for ColorFlowBuilder {
+    fn default() -> Self {
+        ColorFlowBuilder::new()
+    }
+}
+
+impl ColorFlow {
+    pub fn builder() -> ColorFlowBuilder {
+        ColorFlowBuilder::new()
+    }
+}
+
+impl ColorFlowBuilder {
+    pub fn new() -> Self {
+        ColorFlowBuilder {
+            repeat: 1,
+            action: CfAction::Recover,
+            sequence: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Changes to `color` at `brightness` over `millis` milliseconds.
+    pub fn color(self, color: RGB, brightness: u8, millis: u64) -> Self {
+        self.step(
+            millis,
+            FlowTupleMode::Color(ColorFlowTupleMode { color, brightness }),
+        )
+    }
+
+    /// Changes to colour temperature `ct` at `brightness` over `millis`
+    /// milliseconds.
+    pub fn ct(self, ct: u16, brightness: u8, millis: u64) -> Self {
+        self.step(
+            millis,
+            FlowTupleMode::Ct(CtFlowTupleMode { ct, brightness }),
+        )
+    }
+
+    /// Holds the current state for `millis` milliseconds.
+    pub fn sleep(self, millis: u64) -> Self {
+        self.step(millis, FlowTupleMode::Sleep)
+    }
+
+    /// Runs the steps `times` times, or until stopped if 0.
+    pub fn repeat(mut self, times: u16) -> Self {
+        self.repeat = times;
+        self
+    }
+
+    /// Runs the steps until the flow is stopped.
+    pub fn forever(self) -> Self {
+        self.repeat(0)
+    }
+
+    /// What the bulb does once the flow ends.
+    pub fn then(mut self, action: CfAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    pub fn build(self) -> Result<ColorFlow, FlowError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if self.sequence.is_empty() {
+            return Err(FlowError::Empty);
+        }
+
+        // The bulb counts state changes, not runs of the whole sequence.
+        let count = u16::try_from(self.sequence.len())
+            .ok()
+            .and_then(|len| len.checked_mul(self.repeat))
+            .ok_or(FlowError::CountTooLarge)?;
+        Ok(ColorFlow {
+            count,
+            action: self.action,
+            sequence: self.sequence,
+        })
+    }
+
+    /// Fails the flow with `error`, unless an earlier step already did.
+    pub(crate) fn fail(mut self, error: FlowError) -> Self {
+        self.error.get_or_insert(error);
+        self
+    }
+
+    fn step(mut self, millis: u64, mode: FlowTupleMode) -> Self {
+        if self.error.is_none() {
+            self.error = check_step(self.sequence.len(), millis, &mode).err();
+        }
+        self.sequence.push(FlowTuple {
+            duration: Duration::from_millis(millis),
+            mode,
+        });
+        self
+    }
+}
+
+/// Parses a flow expression such as `"1000,1,16711680,100,500,7,0,0"`, the
+/// format `start_cf` takes and Yeelight app exports use. It may start with
+/// the count and action as well, the way the bulb reports its running flow
+/// in `flow_params`. Without them the flow runs once and then recovers.
+///
+/// Parsed flows get the same checks as built ones.
+impl FromStr for ColorFlow {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Err(FlowError::Empty);
+        }
+        let values = s
+            .split(',')
+            .map(str::trim)
+            .enumerate()
+            .map(|(position, value)| {
+                value.parse::<u32>().map_err(|_| FlowError::InvalidValue {
+                    position,
+                    value: value.to_string(),
+                })
+            })
+            .collect::<Result<Vec<u32>, FlowError>>()?;
+
+        let (header, tuples) = match values.len() % 4 {
+            0 => (None, &values[..]),
+            2 => (Some((values[0], values[1])), &values[2..]),
+            _ => {
+                return Err(FlowError::WrongLength {
+                    values: values.len(),
+                })
+            }
+        };
+
+        let mut builder = ColorFlow::builder();
+        for (step, tuple) in tuples.chunks(4).enumerate() {
+            let (millis, mode, value, bright) = (u64::from(tuple[0]), tuple[1], tuple[2], tuple[3]);
+            let bright =
+                u8::try_from(bright).map_err(|_| FlowError::BrightnessOutOfRange { step })?;
+            builder = match mode {
+                1 if value > 0xFF_FFFF => return Err(FlowError::ColorOutOfRange { step }),
+                1 => builder.color(RGB::from(value), bright, millis),
+                2 => {
+                    let ct = u16::try_from(value).map_err(|_| FlowError::CtOutOfRange { step })?;
+                    builder.ct(ct, bright, millis)
+                }
+                7 => builder.sleep(millis),
+                mode => return Err(FlowError::UnknownMode { step, mode }),
+            };
+        }
+
+        let (count, action) = match header {
+            Some(header) => header,
+            None => return builder.build(),
+        };
+        let action = match action {
+            0 => CfAction::Recover,
+            1 => CfAction::Stay,
+            2 => CfAction::TurnOff,
+            action => return Err(FlowError::UnknownAction { action }),
+        };
+        let mut flow = builder.then(action).build()?;
+        flow.count = u16::try_from(count).map_err(|_| FlowError::CountTooLarge)?;
+        Ok(flow)
+    }
+}
+
+impl TryFrom<&str> for ColorFlow {
+    type Error = FlowError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+fn check_step(step: usize, millis: u64, mode: &FlowTupleMode) -> Result<(), FlowError> {
+    if Duration::from_millis(millis) < MINIMUM_CF_DURATION {
+        return Err(FlowError::DurationTooShort { step });
+    }
+    match mode {
+        FlowTupleMode::Color(c) if c.brightness > MAX_BRIGHTNESS => {
+            Err(FlowError::BrightnessOutOfRange { step })
+        }
+        FlowTupleMode::Ct(c) if c.brightness > MAX_BRIGHTNESS => {
+            Err(FlowError::BrightnessOutOfRange { step })
+        }
+        FlowTupleMode::Ct(c) if !(CT_MIN..=CT_MAX).contains(&c.ct) => {
+            Err(FlowError::CtOutOfRange { step })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::FlowError;
+    use crate::{
+        connection::{CfAction, ColorFlow, FlowTupleMode},
+        method_calls::MethodArg,
+        model::BACKGROUND,
+        rgb::RGB,
+    };
+
+    #[test]
+    fn builder_test() {
+        let flow = ColorFlow::builder()
+            .color(RGB { r: 255, g: 0, b: 0 }, 100, 500)
+            .ct(2700, 50, 1000)
+            .sleep(200)
+            .repeat(2)
+            .then(CfAction::TurnOff)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            flow.params(&BACKGROUND).unwrap(),
+            vec![
+                MethodArg::Int(6),
+                MethodArg::Int(2),
+                MethodArg::String("500,1,16711680,100,1000,2,2700,50,200,7,0,0".to_string()),
+            ]
+        );
+
+        let forever = ColorFlow::builder().sleep(50).forever().build().unwrap();
+        assert_eq!(forever.count, 0);
+    }
+
+    #[test]
+    fn parse_test() {
+        let expression = "1000,1,16711680,100,500,7,0,0,2000,2,2700,30";
+        let flow: ColorFlow = expression.parse().unwrap();
+        assert_eq!(flow.count, 3);
+        assert!(matches!(flow.action, CfAction::Recover));
+        assert!(matches!(flow.sequence[1].mode, FlowTupleMode::Sleep));
+        assert_eq!(
+            flow.params(&BACKGROUND).unwrap()[2],
+            MethodArg::String(expression.to_string())
+        );
+
+        // As the bulb reports it in flow_params.
+        let flow = ColorFlow::try_from(" 0, 2, 1000, 1, 255, 100 ").unwrap();
+        assert_eq!(flow.count, 0);
+        assert!(matches!(flow.action, CfAction::TurnOff));
+        assert_eq!(
+            flow.params(&BACKGROUND).unwrap(),
+            vec![
+                MethodArg::Int(0),
+                MethodArg::Int(2),
+                MethodArg::String("1000,1,255,100".to_string()),
+            ]
+        );
+
+        // However long the bulb's own flow is.
+        let expression = vec!["500,7,0,0"; 40].join(",");
+        assert_eq!(expression.parse::<ColorFlow>().unwrap().sequence.len(), 40);
+    }
+
+    #[test]
+    fn parse_errors_test() {
+        let parse = |s: &str| s.parse::<ColorFlow>().err().unwrap();
+        assert_eq!(parse(""), FlowError::Empty);
+        assert_eq!(parse("1000,1,255"), FlowError::WrongLength { values: 3 });
+        assert_eq!(
+            parse("1000,1,red,100"),
+            FlowError::InvalidValue {
+                position: 2,
+                value: "red".to_string()
+            }
+        );
+        assert_eq!(
+            parse("1000,1,-1,100"),
+            FlowError::InvalidValue {
+                position: 2,
+                value: "-1".to_string()
+            }
+        );
+        assert_eq!(
+            parse("1000,1,255,100,1000,3,120,100"),
+            FlowError::UnknownMode { step: 1, mode: 3 }
+        );
+        assert_eq!(
+            parse("1000,1,16777216,100"),
+            FlowError::ColorOutOfRange { step: 0 }
+        );
+        assert_eq!(
+            parse("1000,2,1000,100"),
+            FlowError::CtOutOfRange { step: 0 }
+        );
+        assert_eq!(
+            parse("1000,1,255,300"),
+            FlowError::BrightnessOutOfRange { step: 0 }
+        );
+        assert_eq!(
+            parse("10,1,255,100"),
+            FlowError::DurationTooShort { step: 0 }
+        );
+        assert_eq!(
+            parse("0,5,1000,1,255,100"),
+            FlowError::UnknownAction { action: 5 }
+        );
+    }
+
+    #[test]
+    fn builder_errors_test() {
+        let red = RGB { r: 255, g: 0, b: 0 };
+        assert_eq!(ColorFlow::builder().build().err(), Some(FlowError::Empty));
+        assert_eq!(
+            ColorFlow::builder()
+                .color(red, 100, 500)
+                .color(red, 100, 10)
+                .ct(1000, 50, 500)
+                .build()
+                .err(),
+            Some(FlowError::DurationTooShort { step: 1 })
+        );
+        assert_eq!(
+            ColorFlow::builder().ct(9000, 50, 500).build().err(),
+            Some(FlowError::CtOutOfRange { step: 0 })
+        );
+        assert_eq!(
+            ColorFlow::builder().color(red, 101, 500).build().err(),
+            Some(FlowError::BrightnessOutOfRange { step: 0 })
+        );
+        assert_eq!(
+            ColorFlow::builder()
+                .sleep(50)
+                .sleep(50)
+                .repeat(u16::MAX)
+                .build()
+                .err(),
+            Some(FlowError::CountTooLarge)
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::str::Utf8Error;
+
+const FRAME_TERMINATOR: u8 = b'\n';
+
+/// Buffers bytes read from a bulb and splits them into the `\r\n` terminated
+/// frames the protocol uses. A frame split over several reads is held back
+/// until its terminator arrives, and bytes following the last complete frame
+/// are kept for the next call, so there is no limit on frame length.
+#[derive(Debug, Default)]
+pub struct FrameBuffer {
+    buf: Vec<u8>,
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        FrameBuffer::default()
+    }
+
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Removes and returns the next complete, non-empty frame without its terminator.
+    pub fn next_frame(&mut self) -> Option<Result<String, Utf8Error>> {
+        loop {
+            let end = self.buf.iter().position(|b| *b == FRAME_TERMINATOR)?;
+            let mut frame: Vec<u8> = self.buf.drain(..=end).collect();
+            frame.pop();
+            if frame.last() == Some(&b'\r') {
+                frame.pop();
+            }
+
+            if frame.iter().all(|b| b.is_ascii_whitespace()) {
+                continue;
+            }
+
+            return Some(std::str::from_utf8(&frame).map(|s| s.trim().to_string()));
+        }
+    }
+
+    /// Number of bytes held back waiting for a terminator.
+    pub fn pending(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameBuffer;
+
+    #[test]
+    fn fragmented_frame_test() {
+        let mut frames = FrameBuffer::new();
+
+        frames.extend(b"{\"id\":1, \"res");
+        assert!(frames.next_frame().is_none());
+
+        frames.extend(b"ult\":[\"ok\"]}\r");
+        assert!(frames.next_frame().is_none());
+
+        frames.extend(b"\n");
+        assert_eq!(
+            frames.next_frame().unwrap().unwrap(),
+            "{\"id\":1, \"result\":[\"ok\"]}"
+        );
+        assert_eq!(frames.pending(), 0);
+    }
+
+    #[test]
+    fn coalesced_frames_test() {
+        let mut frames = FrameBuffer::new();
+
+        frames.extend(b"{\"id\":1}\r\n\r\n{\"id\":2}\r\n{\"id\":");
+
+        assert_eq!(frames.next_frame().unwrap().unwrap(), "{\"id\":1}");
+        assert_eq!(frames.next_frame().unwrap().unwrap(), "{\"id\":2}");
+        assert!(frames.next_frame().is_none());
+        assert_eq!(frames.pending(), 6);
+
+        frames.extend(b"3}\r\n");
+        assert_eq!(frames.next_frame().unwrap().unwrap(), "{\"id\":3}");
+    }
+
+    #[test]
+    fn long_frame_test() {
+        let mut frames = FrameBuffer::new();
+        let long = "x".repeat(10_000);
+
+        for chunk in long.as_bytes().chunks(2048) {
+            frames.extend(chunk);
+            assert!(frames.next_frame().is_none());
+        }
+        frames.extend(b"\r\n");
+
+        assert_eq!(frames.next_frame().unwrap().unwrap(), long);
+    }
+}
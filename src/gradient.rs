@@ -0,0 +1,421 @@
+//! Fades through a list of colours as a color flow, with the in-between
+//! colours worked out here and sent to the bulb as steps:
+//!
+//! ```
+//! use libyee::gradient::{gradient, Interpolation, Stop};
+//! use libyee::rgb::RGB;
+//!
+//! let sunset = [
+//!     Stop::Color(RGB { r: 255, g: 120, b: 0 }, 100),
+//!     Stop::Color(RGB { r: 128, g: 0, b: 128 }, 60),
+//!     Stop::Color(RGB { r: 0, g: 0, b: 128 }, 10),
+//! ];
+//! let flow = gradient(&sunset, Interpolation::Lab, 12, 60_000).unwrap();
+//! assert_eq!(flow.sequence.len(), 12);
+//! ```
+
+use std::convert::TryFrom;
+
+use crate::{
+    color::{from_hsv, hue_chroma, Kelvin, Lab},
+    connection::{
+        Brightness, CfAction, ColorFlow, Ct, CT_MAX, CT_MIN, MAX_BRIGHTNESS, MINIMUM_CF_DURATION,
+    },
+    flow::{ColorFlowBuilder, FlowError},
+    lightmode::HSV,
+    rgb::RGB,
+};
+
+/// The most steps [`ColorFlowBuilder::gradient`] fades in, so a long fade
+/// doesn't turn into a huge `start_cf` request.
+pub const MAX_GRADIENT_STEPS: usize = 32;
+
+/// A colour the gradient passes through, at a brightness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Color(RGB, Brightness),
+    HSV(HSV, Brightness),
+    Ct(Ct, Brightness),
+}
+
+/// The colour space the in-between colours are mixed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Straight through sRGB. Cheap, but fades between distant hues pass
+    /// through muddy greys.
+    Rgb,
+    /// Around the colour wheel the short way, keeping colours saturated.
+    Hsv,
+    /// Through CIE L*a*b*, so each step looks about as big as the next.
+    Lab,
+}
+
+impl Stop {
+    fn brightness(&self) -> Brightness {
+        match self {
+            Stop::Color(_, brightness) | Stop::HSV(_, brightness) | Stop::Ct(_, brightness) => {
+                *brightness
+            }
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        let valid = match self {
+            Stop::Color(_, _) => true,
+            Stop::HSV(hsv, _) => hsv.validate(),
+            Stop::Ct(ct, _) => (CT_MIN..=CT_MAX).contains(ct),
+        };
+        valid && self.brightness() <= MAX_BRIGHTNESS
+    }
+
+    fn rgb(&self) -> RGB {
+        match self {
+            Stop::Color(rgb, _) => *rgb,
+            Stop::HSV(hsv, _) => {
+                from_hsv(f64::from(hsv.hue), f64::from(hsv.saturation) / 100.0, 1.0)
+            }
+            Stop::Ct(ct, _) => RGB::from(Kelvin(*ct)),
+        }
+    }
+}
+
+fn mix(from: f64, to: f64, progress: f64) -> f64 {
+    from + (to - from) * progress
+}
+
+impl Interpolation {
+    /// The stop's colour as three numbers to mix.
+    fn point(self, stop: &Stop) -> [f64; 3] {
+        match (self, stop) {
+            (Interpolation::Hsv, Stop::HSV(hsv, _)) => {
+                [f64::from(hsv.hue), f64::from(hsv.saturation) / 100.0, 1.0]
+            }
+            (Interpolation::Hsv, _) => {
+                let (hue, chroma, max) = hue_chroma(stop.rgb());
+                let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+                [hue, saturation, max]
+            }
+            (Interpolation::Rgb, _) => {
+                let rgb = stop.rgb();
+                [rgb.r, rgb.g, rgb.b].map(f64::from)
+            }
+            (Interpolation::Lab, _) => {
+                let lab = Lab::from(stop.rgb());
+                [lab.l, lab.a, lab.b]
+            }
+        }
+    }
+
+    fn mix(self, from: [f64; 3], to: [f64; 3], progress: f64) -> [f64; 3] {
+        let mut mixed = [0, 1, 2].map(|i| mix(from[i], to[i], progress));
+        if self == Interpolation::Hsv {
+            // A grey has no hue of its own and takes the other end's.
+            let (from_hue, to_hue) = match (from[1] == 0.0, to[1] == 0.0) {
+                (true, false) => (to[0], to[0]),
+                (false, true) => (from[0], from[0]),
+                _ => (from[0], to[0]),
+            };
+            let turn = (to_hue - from_hue + 540.0).rem_euclid(360.0) - 180.0;
+            mixed[0] = (from_hue + turn * progress).rem_euclid(360.0);
+        }
+        mixed
+    }
+
+    fn color(self, point: [f64; 3]) -> RGB {
+        match self {
+            Interpolation::Rgb => {
+                let [r, g, b] = point.map(|c| c.round().clamp(0.0, 255.0) as u8);
+                RGB { r, g, b }
+            }
+            Interpolation::Hsv => from_hsv(point[0], point[1], point[2]),
+            Interpolation::Lab => RGB::from(Lab {
+                l: point[0],
+                a: point[1],
+                b: point[2],
+            }),
+        }
+    }
+}
+
+/// The pair of stops around `progress`, from 0 to 1 along the gradient,
+/// and how far it is from the first to the second.
+fn segment(stops: &[Stop], progress: f64) -> (&Stop, &Stop, f64) {
+    let position = progress * (stops.len() - 1) as f64;
+    let first = (position.floor() as usize).min(stops.len().saturating_sub(2));
+    let second = (first + 1).min(stops.len() - 1);
+    (&stops[first], &stops[second], position - first as f64)
+}
+
+impl ColorFlowBuilder {
+    /// Fades through `stops` in `steps` steps taking `millis` in all, the
+    /// first step fading to the first stop and the last to the last stop.
+    /// Brightness fades along with the colour. When every stop is a colour
+    /// temperature the steps are colour temperatures too, fading in Kelvin.
+    ///
+    /// There are never more than [`MAX_GRADIENT_STEPS`] steps, nor more than
+    /// can each last [`MINIMUM_CF_DURATION`], so asking for many steps gets
+    /// as smooth a fade as the bulb allows.
+    pub fn gradient(
+        self,
+        stops: &[Stop],
+        interpolation: Interpolation,
+        steps: usize,
+        millis: u64,
+    ) -> Self {
+        if let Some(stop) = stops.iter().position(|stop| !stop.is_valid()) {
+            return self.fail(FlowError::InvalidStop { stop });
+        }
+        if stops.is_empty() {
+            return self;
+        }
+
+        let longest = millis / MINIMUM_CF_DURATION.as_millis() as u64;
+        let steps = steps
+            .min(MAX_GRADIENT_STEPS)
+            .min(usize::try_from(longest).unwrap_or(usize::MAX))
+            .max(1);
+        let all_ct = stops.iter().all(|stop| matches!(stop, Stop::Ct(_, _)));
+
+        (0..steps).fold(self, |flow, i| {
+            // Spread the odd milliseconds over the first steps.
+            let step_millis = millis / steps as u64 + u64::from((i as u64) < millis % steps as u64);
+            let progress = if steps == 1 {
+                1.0
+            } else {
+                i as f64 / (steps - 1) as f64
+            };
+            let (from, to, progress) = segment(stops, progress);
+            let brightness = mix(
+                f64::from(from.brightness()),
+                f64::from(to.brightness()),
+                progress,
+            )
+            .round() as u8;
+
+            match (from, to) {
+                (Stop::Ct(from, _), Stop::Ct(to, _)) if all_ct => {
+                    let ct = mix(f64::from(*from), f64::from(*to), progress).round() as u16;
+                    flow.ct(ct, brightness, step_millis)
+                }
+                _ => {
+                    let point = interpolation.mix(
+                        interpolation.point(from),
+                        interpolation.point(to),
+                        progress,
+                    );
+                    flow.color(interpolation.color(point), brightness, step_millis)
+                }
+            }
+        })
+    }
+}
+
+/// A flow that fades through `stops` once and stays on the last, see
+/// [`ColorFlowBuilder::gradient`].
+pub fn gradient(
+    stops: &[Stop],
+    interpolation: Interpolation,
+    steps: usize,
+    millis: u64,
+) -> Result<ColorFlow, FlowError> {
+    ColorFlow::builder()
+        .gradient(stops, interpolation, steps, millis)
+        .then(CfAction::Stay)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{gradient, Interpolation, Stop, MAX_GRADIENT_STEPS};
+    use crate::{
+        connection::{ColorFlow, ColorFlowTupleMode, CtFlowTupleMode, FlowTupleMode},
+        flow::FlowError,
+        lightmode::HSV,
+        rgb::RGB,
+    };
+
+    const RED: RGB = RGB { r: 255, g: 0, b: 0 };
+    const BLUE: RGB = RGB { r: 0, g: 0, b: 255 };
+
+    fn colors(flow: &ColorFlow) -> Vec<(RGB, u8)> {
+        flow.sequence
+            .iter()
+            .map(|tuple| match &tuple.mode {
+                FlowTupleMode::Color(ColorFlowTupleMode { color, brightness }) => {
+                    (*color, *brightness)
+                }
+                _ => panic!("not a colour step"),
+            })
+            .collect()
+    }
+
+    fn red_to_blue(interpolation: Interpolation) -> Vec<(RGB, u8)> {
+        let stops = [Stop::Color(RED, 100), Stop::Color(BLUE, 20)];
+        colors(&gradient(&stops, interpolation, 3, 3000).unwrap())
+    }
+
+    #[test]
+    fn rgb_gradient_test() {
+        let stops = [Stop::Color(RED, 100), Stop::Color(BLUE, 20)];
+        let flow = gradient(&stops, Interpolation::Rgb, 3, 3001).unwrap();
+        let durations: Vec<Duration> = flow.sequence.iter().map(|t| t.duration).collect();
+        assert_eq!(
+            durations,
+            vec![1001, 1000, 1000]
+                .into_iter()
+                .map(Duration::from_millis)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(flow.count, 3);
+
+        let purple = RGB {
+            r: 128,
+            g: 0,
+            b: 128,
+        };
+        assert_eq!(
+            red_to_blue(Interpolation::Rgb),
+            vec![(RED, 100), (purple, 60), (BLUE, 20)]
+        );
+    }
+
+    #[test]
+    fn hsv_gradient_test() {
+        // Red to blue the short way round is through magenta, not green.
+        let magenta = RGB {
+            r: 255,
+            g: 0,
+            b: 255,
+        };
+        assert_eq!(red_to_blue(Interpolation::Hsv)[1].0, magenta);
+
+        let stops = [
+            Stop::HSV(
+                HSV {
+                    hue: 350,
+                    saturation: 100,
+                },
+                100,
+            ),
+            Stop::HSV(
+                HSV {
+                    hue: 10,
+                    saturation: 100,
+                },
+                100,
+            ),
+        ];
+        let flow = gradient(&stops, Interpolation::Hsv, 3, 3000).unwrap();
+        assert_eq!(colors(&flow)[1].0, RED);
+
+        // Fading from white keeps the hue of the colour it fades to.
+        let white = RGB {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let stops = [Stop::Color(white, 100), Stop::Color(BLUE, 100)];
+        let flow = gradient(&stops, Interpolation::Hsv, 3, 3000).unwrap();
+        assert_eq!(
+            colors(&flow)[1].0,
+            RGB {
+                r: 128,
+                g: 128,
+                b: 255
+            }
+        );
+    }
+
+    #[test]
+    fn lab_gradient_test() {
+        let black = RGB { r: 0, g: 0, b: 0 };
+        let white = RGB {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let stops = [Stop::Color(black, 100), Stop::Color(white, 100)];
+        let flow = gradient(&stops, Interpolation::Lab, 3, 3000).unwrap();
+        // L* 50 is a darker grey than halfway in sRGB.
+        assert_eq!(
+            colors(&flow)[1].0,
+            RGB {
+                r: 119,
+                g: 119,
+                b: 119
+            }
+        );
+    }
+
+    #[test]
+    fn stops_test() {
+        let green = RGB { r: 0, g: 255, b: 0 };
+        let stops = [
+            Stop::Color(RED, 100),
+            Stop::Color(green, 100),
+            Stop::Color(BLUE, 100),
+        ];
+        let steps: Vec<RGB> = colors(&gradient(&stops, Interpolation::Rgb, 5, 5000).unwrap())
+            .into_iter()
+            .map(|(color, _)| color)
+            .collect();
+        assert_eq!(steps[0], RED);
+        assert_eq!(steps[2], green);
+        assert_eq!(steps[4], BLUE);
+
+        let ct = [Stop::Ct(2700, 100), Stop::Ct(6500, 100)];
+        let flow = gradient(&ct, Interpolation::Lab, 3, 3000).unwrap();
+        let cts: Vec<u16> = flow
+            .sequence
+            .iter()
+            .map(|tuple| match &tuple.mode {
+                FlowTupleMode::Ct(CtFlowTupleMode { ct, .. }) => *ct,
+                _ => panic!("not a colour temperature step"),
+            })
+            .collect();
+        assert_eq!(cts, vec![2700, 4600, 6500]);
+
+        // Mixed with a colour, colour temperatures are mixed as their RGB.
+        let mixed = [Stop::Ct(6500, 100), Stop::Color(RED, 100)];
+        let flow = gradient(&mixed, Interpolation::Rgb, 2, 1000).unwrap();
+        assert_eq!(colors(&flow)[1].0, RED);
+    }
+
+    #[test]
+    fn limits_test() {
+        let stops = [Stop::Color(RED, 100), Stop::Color(BLUE, 100)];
+        let flow = gradient(&stops, Interpolation::Rgb, 100, 1000).unwrap();
+        assert_eq!(flow.sequence.len(), 20);
+
+        let flow = gradient(&stops, Interpolation::Rgb, 100, 60_000).unwrap();
+        assert_eq!(flow.sequence.len(), MAX_GRADIENT_STEPS);
+
+        let flow = ColorFlow::builder()
+            .sleep(1000)
+            .gradient(&stops, Interpolation::Rgb, 100, 60_000)
+            .build()
+            .unwrap();
+        assert_eq!(flow.sequence.len(), MAX_GRADIENT_STEPS + 1);
+
+        assert_eq!(
+            gradient(&stops, Interpolation::Rgb, 3, 40).err(),
+            Some(FlowError::DurationTooShort { step: 0 })
+        );
+        assert_eq!(
+            gradient(
+                &[Stop::Color(RED, 100), Stop::Ct(9000, 100)],
+                Interpolation::Rgb,
+                3,
+                3000
+            )
+            .err(),
+            Some(FlowError::InvalidStop { stop: 1 })
+        );
+        assert_eq!(
+            gradient(&[], Interpolation::Rgb, 3, 3000).err(),
+            Some(FlowError::Empty)
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    io::{Read, Write},
+    panic, thread,
+};
+
+use crate::{
+    bulb::Bulb,
+    connection::{
+        BulbConnection, ColorFlow, MethodCallError, PowerMode, Scene, StringVecResponse,
+        TransitionMode,
+    },
+    lightmode::HSV,
+    method::Method,
+    power::Power,
+    rgb::RGB,
+};
+
+/// Bulbs controlled together, such as the lights of one room. A command
+/// is sent to every member at the same time, each on its own thread, so
+/// the bulbs change together instead of one after another.
+pub struct BulbGroup<T: Read + Write + Send> {
+    members: Vec<BulbConnection<T>>,
+}
+
+/// What a command did on one member of a group.
+#[derive(Debug)]
+pub enum Outcome<R> {
+    /// The command was sent and this is what the call returned.
+    Sent(Result<R, MethodCallError>),
+    /// The bulb doesn't support the command, so it wasn't contacted.
+    Skipped,
+}
+
+#[derive(Debug)]
+pub struct MemberResult<R> {
+    pub bulb_id: String,
+    pub outcome: Outcome<R>,
+}
+
+impl<R> MemberResult<R> {
+    pub fn is_ok(&self) -> bool {
+        matches!(self.outcome, Outcome::Sent(Ok(_)))
+    }
+}
+
+impl<T: Read + Write + Send> Default for BulbGroup<T> {
+    fn default() -> Self {
+        BulbGroup::new()
+    }
+}
+
+impl<T: Read + Write + Send> BulbGroup<T> {
+    pub fn new() -> Self {
+        BulbGroup {
+            members: Vec::new(),
+        }
+    }
+
+    pub fn from_connections(members: Vec<BulbConnection<T>>) -> Self {
+        BulbGroup { members }
+    }
+
+    pub fn add(&mut self, member: BulbConnection<T>) {
+        self.members.push(member);
+    }
+
+    /// Takes the member with the given bulb id out of the group.
+    pub fn remove(&mut self, bulb_id: &str) -> Option<BulbConnection<T>> {
+        let index = self.members.iter().position(|m| m.bulb.id == bulb_id)?;
+        Some(self.members.remove(index))
+    }
+
+    pub fn members(&self) -> &[BulbConnection<T>] {
+        &self.members
+    }
+
+    pub fn members_mut(&mut self) -> &mut [BulbConnection<T>] {
+        &mut self.members
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Members whose bulb doesn't list `method` in its `support`, for
+    /// checking a command against the whole group before sending it.
+    pub fn unsupported(&self, method: Method) -> Vec<&Bulb> {
+        self.members
+            .iter()
+            .map(|m| &m.bulb)
+            .filter(|bulb| !bulb.support.contains(&method))
+            .collect()
+    }
+
+    /// Runs `command` on every member that supports `method`, all at once,
+    /// and waits for each of them. Members that don't support it are
+    /// skipped. Results are in member order.
+    pub fn run<R, F>(&mut self, method: Method, command: F) -> Vec<MemberResult<R>>
+    where
+        R: Send,
+        F: Fn(&mut BulbConnection<T>) -> Result<R, MethodCallError> + Sync,
+    {
+        let command = &command;
+        thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .members
+                .iter_mut()
+                .map(|member| {
+                    let bulb_id = member.bulb.id.clone();
+                    let handle = if member.bulb.support.contains(&method) {
+                        Some(scope.spawn(move || command(member)))
+                    } else {
+                        None
+                    };
+                    (bulb_id, handle)
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|(bulb_id, handle)| MemberResult {
+                    bulb_id,
+                    outcome: match handle {
+                        Some(handle) => Outcome::Sent(
+                            handle
+                                .join()
+                                .unwrap_or_else(|panic| panic::resume_unwind(panic)),
+                        ),
+                        None => Outcome::Skipped,
+                    },
+                })
+                .collect()
+        })
+    }
+
+    pub fn set_ct_abx(
+        &mut self,
+        ct_value: u16,
+        mode: TransitionMode,
+    ) -> Vec<MemberResult<StringVecResponse>> {
+        self.run(Method::SetCtAbx, |m| m.set_ct_abx(ct_value, mode))
+    }
+
+    pub fn set_rgb(
+        &mut self,
+        rgb: &RGB,
+        mode: TransitionMode,
+    ) -> Vec<MemberResult<StringVecResponse>> {
+        self.run(Method::SetRgb, |m| m.set_rgb(rgb, mode))
+    }
+
+    pub fn set_hsv(
+        &mut self,
+        hsv: &HSV,
+        mode: TransitionMode,
+    ) -> Vec<MemberResult<StringVecResponse>> {
+        self.run(Method::SetHsv, |m| m.set_hsv(hsv, mode))
+    }
+
+    pub fn set_bright(
+        &mut self,
+        brightness: u8,
+        mode: TransitionMode,
+    ) -> Vec<MemberResult<StringVecResponse>> {
+        self.run(Method::SetBright, |m| m.set_bright(brightness, mode))
+    }
+
+    pub fn set_power(
+        &mut self,
+        power: Power,
+        trans_mode: TransitionMode,
+        power_mode: Option<PowerMode>,
+    ) -> Vec<MemberResult<StringVecResponse>> {
+        self.run(Method::SetPower, |m| {
+            m.set_power(power, trans_mode, power_mode)
+        })
+    }
+
+    pub fn toggle(&mut self) -> Vec<MemberResult<StringVecResponse>> {
+        self.run(Method::Toggle, |m| m.toggle())
+    }
+
+    pub fn start_cf(&mut self, cf: &ColorFlow) -> Vec<MemberResult<StringVecResponse>> {
+        self.run(Method::StartCf, |m| m.start_cf(cf))
+    }
+
+    pub fn stop_cf(&mut self) -> Vec<MemberResult<StringVecResponse>> {
+        self.run(Method::StopCf, |m| m.stop_cf())
+    }
+
+    pub fn set_scene(&mut self, scene: &Scene<'_, '_>) -> Vec<MemberResult<StringVecResponse>> {
+        self.run(Method::SetScene, |m| m.set_scene(scene))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BulbGroup, Outcome};
+    use crate::{
+        bulb::Bulb,
+        connection::{BulbConnection, MethodCallError, TransitionMode},
+        method::Method,
+        rgb::RGB,
+        test_utils::{bulb_with_methods, ScriptedConnection},
+    };
+
+    fn member(id: &str, methods: &[Method], reads: &[&str]) -> BulbConnection<ScriptedConnection> {
+        let bulb = Bulb {
+            id: id.to_string(),
+            ..bulb_with_methods(methods)
+        };
+        BulbConnection::from_connection(bulb, ScriptedConnection::new(reads))
+    }
+
+    #[test]
+    fn fan_out_test() {
+        let ok = "{\"id\":1, \"result\":[\"ok\"]}\r\n";
+        let mut group = BulbGroup::from_connections(vec![
+            member("a", &[Method::SetRgb], &[ok]),
+            member("b", &[Method::SetPower], &[ok]),
+            member("c", &[Method::SetRgb], &[]),
+        ]);
+
+        let unsupported: Vec<&str> = group
+            .unsupported(Method::SetRgb)
+            .iter()
+            .map(|bulb| bulb.id.as_str())
+            .collect();
+        assert_eq!(unsupported, vec!["b"]);
+
+        let red = RGB { r: 255, g: 0, b: 0 };
+        let results = group.set_rgb(&red, TransitionMode::Sudden);
+        assert_eq!(results.len(), 3);
+
+        assert_eq!(results[0].bulb_id, "a");
+        assert!(results[0].is_ok());
+        assert_eq!(
+            group.members()[0].bulb.color_mode.to_props()["rgb"],
+            "16711680"
+        );
+
+        assert!(matches!(results[1].outcome, Outcome::Skipped));
+        assert!(group.members()[1]
+            .connection
+            .lock()
+            .unwrap()
+            .written
+            .is_empty());
+
+        // The stream closed without a reply.
+        match &results[2].outcome {
+            Outcome::Sent(Err(err)) => {
+                assert!(matches!(err.kind(), MethodCallError::IOError(_)));
+                assert_eq!(err.bulb_id(), Some("c"));
+            }
+            other => panic!("unexpected outcome {:?}", other),
+        }
+    }
+
+    #[test]
+    fn remove_test() {
+        let mut group = BulbGroup::new();
+        group.add(member("a", &[], &[]));
+        group.add(member("b", &[], &[]));
+
+        assert_eq!(group.remove("a").unwrap().bulb.id, "a");
+        assert!(group.remove("a").is_none());
+        assert_eq!(group.len(), 1);
+    }
+}
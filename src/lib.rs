@@ -1,8 +1,33 @@
+#[cfg(feature = "async")]
+pub mod async_connection;
+#[cfg(feature = "async")]
+pub mod async_method_calls;
 pub mod bulb;
+pub mod color;
+pub mod connection;
+pub mod effects;
+pub mod event;
+pub mod flow;
+pub mod frame;
+pub mod gradient;
+pub mod group;
+mod light_methods;
 pub mod lightmode;
 pub mod method;
+pub mod method_calls;
+pub mod model;
+pub mod music;
+pub mod name;
+pub mod pipeline;
 pub mod power;
+pub mod property;
+pub mod rate_limit;
+pub mod reconnect;
+pub mod render;
 pub mod rgb;
 pub mod search;
-pub mod connection;
-pub mod method_calls;
\ No newline at end of file
+#[cfg(feature = "serde")]
+mod serde_impls;
+pub mod snapshot;
+#[cfg(test)]
+mod test_utils;
@@ -0,0 +1,161 @@
+//! The methods every way of talking to a bulb offers, written once.
+//!
+//! `light_methods!(dispatch -> Output, bulb)` expands, inside an `impl`
+//! block, to one `pub fn` per method that checks its arguments and hands
+//! them to `self.dispatch(method, args)`. `bulb` is the path from `self` to
+//! the [`Bulb`](crate::bulb::Bulb) whose capabilities the main light's
+//! arguments are checked against. Put `async` in front for `async fn`s that
+//! await the dispatch.
+//!
+//! `control_methods!` takes the same input and adds the methods that only
+//! make sense with a reply to wait for, such as timers and the name.
+
+macro_rules! light_methods {
+    (@methods [] $dispatch:ident [$output:ty] $bulb:tt $(
+        $(#[$attr:meta])*
+        fn $name:ident($($arg:ident: $ty:ty),*) => $method:ident($($args:tt)*);
+    )*) => {$(
+        $(#[$attr])*
+        pub fn $name(&mut self, $($arg: $ty),*) -> $output {
+            self.$dispatch(
+                $crate::method::Method::$method,
+                $crate::light_methods::light_methods!(@args self $bulb $($args)*),
+            )
+        }
+    )*};
+    (@methods [async] $dispatch:ident [$output:ty] $bulb:tt $(
+        $(#[$attr:meta])*
+        fn $name:ident($($arg:ident: $ty:ty),*) => $method:ident($($args:tt)*);
+    )*) => {$(
+        $(#[$attr])*
+        pub async fn $name(&mut self, $($arg: $ty),*) -> $output {
+            self.$dispatch(
+                $crate::method::Method::$method,
+                $crate::light_methods::light_methods!(@args self $bulb $($args)*),
+            )
+            .await
+        }
+    )*};
+
+    // `|light| args` builds the arguments with the main light's capabilities.
+    (@args $this:ident [$($field:ident).+] |$light:ident| $args:expr) => {{
+        let $light = &$this.$($field).+.capabilities();
+        $args
+    }};
+    (@args $this:ident $bulb:tt $args:expr) => {
+        $args
+    };
+
+    (@light $mode:tt $dispatch:ident $output:tt $bulb:tt) => {
+        $crate::light_methods::light_methods! { @methods $mode $dispatch $output $bulb
+            /// This method is used to change the color temperature of a smart LED.
+            /// "ct_value" is the target color temperature. The type is integer and
+            /// range depends on the model, 1700 ~ 6500 (k) at most, see
+            /// [`Bulb::capabilities`](crate::bulb::Bulb::capabilities).
+            /// Smooth transition duration in milliseconds should be between 30 and i32::MAX.
+            fn set_ct_abx(ct_value: u16, mode: $crate::connection::TransitionMode)
+                => SetCtAbx(|light| $crate::method_calls::ct_abx_args(ct_value, light, mode));
+            fn set_rgb(rgb: &$crate::rgb::RGB, mode: $crate::connection::TransitionMode)
+                => SetRgb(|light| $crate::method_calls::rgb_args(rgb, light, mode));
+            fn set_hsv(hsv: &$crate::lightmode::HSV, mode: $crate::connection::TransitionMode)
+                => SetHsv(|light| $crate::method_calls::hsv_args(hsv, light, mode));
+            fn set_bright(brightness: u8, mode: $crate::connection::TransitionMode)
+                => SetBright($crate::method_calls::bright_args(brightness, mode));
+            fn set_power(
+                power: $crate::power::Power,
+                trans_mode: $crate::connection::TransitionMode,
+                power_mode: Option<$crate::connection::PowerMode>
+            ) => SetPower(|light| {
+                $crate::method_calls::power_args(power, light, trans_mode, power_mode)
+            });
+            fn toggle() => Toggle(Ok(vec![]));
+            fn set_default() => SetDefault(Ok(vec![]));
+            fn start_cf(cf: &$crate::connection::ColorFlow) => StartCf(|light| cf.params(light));
+            fn stop_cf() => StopCf(Ok(vec![]));
+            fn set_scene(scene: &$crate::connection::Scene<'_, '_>)
+                => SetScene(|light| scene.params(light));
+            fn set_adjust(
+                prop: &$crate::connection::AdjustableProp,
+                action: &$crate::connection::AdjustAction
+            ) => SetAdjust(Ok($crate::method_calls::set_adjust_args(prop, action)));
+            fn bg_set_ct_abx(ct_value: u16, mode: $crate::connection::TransitionMode)
+                => BgSetCtAbx($crate::method_calls::ct_abx_args(
+                    ct_value,
+                    &$crate::model::BACKGROUND,
+                    mode,
+                ));
+            fn bg_set_rgb(rgb: &$crate::rgb::RGB, mode: $crate::connection::TransitionMode)
+                => BgSetRgb($crate::method_calls::rgb_args(rgb, &$crate::model::BACKGROUND, mode));
+            fn bg_set_hsv(hsv: &$crate::lightmode::HSV, mode: $crate::connection::TransitionMode)
+                => BgSetHsv($crate::method_calls::hsv_args(hsv, &$crate::model::BACKGROUND, mode));
+            fn bg_set_bright(brightness: u8, mode: $crate::connection::TransitionMode)
+                => BgSetBright($crate::method_calls::bright_args(brightness, mode));
+            fn bg_set_power(
+                power: $crate::power::Power,
+                trans_mode: $crate::connection::TransitionMode,
+                power_mode: Option<$crate::connection::PowerMode>
+            ) => BgSetPower($crate::method_calls::power_args(
+                power,
+                &$crate::model::BACKGROUND,
+                trans_mode,
+                power_mode,
+            ));
+            fn bg_toggle() => BgToggle(Ok(vec![]));
+            fn bg_set_default() => BgSetDefault(Ok(vec![]));
+            fn bg_start_cf(cf: &$crate::connection::ColorFlow)
+                => BgStartCf(cf.params(&$crate::model::BACKGROUND));
+            fn bg_stop_cf() => BgStopCf(Ok(vec![]));
+            fn bg_set_scene(scene: &$crate::connection::Scene<'_, '_>)
+                => BgSetScene(scene.params(&$crate::model::BACKGROUND));
+            fn bg_set_adjust(
+                prop: &$crate::connection::AdjustableProp,
+                action: &$crate::connection::AdjustAction
+            ) => BgSetAdjust(Ok($crate::method_calls::set_adjust_args(prop, action)));
+            fn dev_toggle() => DevToggle(Ok(vec![]));
+            fn adjust_bright(percentage: i8, duration: &std::time::Duration)
+                => AdjustBright($crate::method_calls::adjust_args(percentage, duration));
+            fn adjust_ct(percentage: i8, duration: &std::time::Duration)
+                => AdjustCt($crate::method_calls::adjust_args(percentage, duration));
+            fn adjust_color(percentage: i8, duration: &std::time::Duration)
+                => AdjustColor($crate::method_calls::adjust_args(percentage, duration));
+            fn bg_adjust_bright(percentage: i8, duration: &std::time::Duration)
+                => BgAdjustBright($crate::method_calls::adjust_args(percentage, duration));
+            fn bg_adjust_ct(percentage: i8, duration: &std::time::Duration)
+                => BgAdjustCt($crate::method_calls::adjust_args(percentage, duration));
+            fn bg_adjust_color(percentage: i8, duration: &std::time::Duration)
+                => BgAdjustColor($crate::method_calls::adjust_args(percentage, duration));
+        }
+    };
+    (@control $mode:tt $dispatch:ident $output:tt $bulb:tt) => {
+        $crate::light_methods::light_methods! { @methods $mode $dispatch $output $bulb
+            /// Usage: This method is used to start a timer job on the smart LED.
+            fn cron_add(cron: &$crate::connection::Cron)
+                => CronAdd(Ok($crate::method_calls::cron_add_args(cron)));
+            fn cron_del(cron_type: &$crate::connection::CronType)
+                => CronDel(Ok($crate::method_calls::cron_type_args(cron_type)));
+            /// Stores the name on the bulb, base64 encoded if it isn't ASCII.
+            /// Fails with `BadRequest` when it doesn't fit, see
+            /// [`encode_name`](crate::name::encode_name).
+            fn set_name(name: &str) => SetName($crate::method_calls::name_args(name));
+        }
+    };
+
+    (async $dispatch:ident -> $output:ty, $($field:ident).+) => {
+        $crate::light_methods::light_methods!(@light [async] $dispatch [$output] [$($field).+]);
+    };
+    ($dispatch:ident -> $output:ty, $($field:ident).+) => {
+        $crate::light_methods::light_methods!(@light [] $dispatch [$output] [$($field).+]);
+    };
+}
+
+macro_rules! control_methods {
+    (async $dispatch:ident -> $output:ty, $($field:ident).+) => {
+        $crate::light_methods::light_methods!(@control [async] $dispatch [$output] [$($field).+]);
+    };
+    ($dispatch:ident -> $output:ty, $($field:ident).+) => {
+        $crate::light_methods::light_methods!(@control [] $dispatch [$output] [$($field).+]);
+    };
+}
+
+pub(crate) use control_methods;
+pub(crate) use light_methods;
@@ -1,8 +1,10 @@
 use crate::rgb::RGB;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HSV {
     // Current hue value. The range of this value is 0 to 359.
     pub hue: u16,
@@ -16,14 +18,28 @@ const MAX_SAT: u8 = 100;
 
 impl HSV {
     pub fn validate(&self) -> bool {
-        return self.hue <= MAX_HUE && self.saturation <= MAX_SAT;
+        self.hue <= MAX_HUE && self.saturation <= MAX_SAT
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// `hsv(300, 50)`, or with `{:#}` the hex of the colour at full value. An
+/// invalid HSV has no hex and is always written the first way.
+impl fmt::Display for HSV {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match RGB::try_from(*self) {
+            Ok(rgb) if f.alternate() => write!(f, "{:#}", rgb),
+            _ => write!(f, "hsv({}, {})", self.hue, self.saturation),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum LightMode {
     Color(RGB),
     // Current color temperature value.
+    #[cfg_attr(feature = "serde", serde(rename = "ct"))]
     ColorTemperature(u16),
     Hsv(HSV),
 }
@@ -35,44 +51,58 @@ impl fmt::Display for LightMode {
 }
 
 impl LightMode {
+    /// The properties `parse` reads the mode from.
+    pub(crate) fn to_props(&self) -> HashMap<String, String> {
+        let props: Vec<(&str, String)> = match self {
+            LightMode::Color(rgb) => vec![
+                ("color_mode", "1".into()),
+                ("rgb", u32::from(rgb).to_string()),
+            ],
+            LightMode::ColorTemperature(ct) => {
+                vec![("color_mode", "2".into()), ("ct", ct.to_string())]
+            }
+            LightMode::Hsv(hsv) => vec![
+                ("color_mode", "3".into()),
+                ("hue", hsv.hue.to_string()),
+                ("sat", hsv.saturation.to_string()),
+            ],
+        };
+        props
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect()
+    }
+
     pub fn parse(response_map: &HashMap<String, String>) -> Option<LightMode> {
         response_map
             .get("color_mode")
-            .map(|cm| cm.parse::<u8>().ok())
-            .flatten()
-            .map(|cm| match cm {
+            .and_then(|cm| cm.parse::<u8>().ok())
+            .and_then(|cm| match cm {
                 1 => response_map
                     .get("rgb")
-                    .map(|rgb| rgb.parse::<u32>().ok())
-                    .flatten()
-                    .map(|rgb| RGB::from(rgb))
-                    .map(|rgb| LightMode::Color(rgb)),
+                    .and_then(|rgb| rgb.parse::<u32>().ok())
+                    .map(RGB::from)
+                    .map(LightMode::Color),
                 2 => response_map
                     .get("ct")
-                    .map(|ct| ct.parse::<u16>().ok())
-                    .flatten()
-                    .map(|ct| LightMode::ColorTemperature(ct)),
-                3 => response_map
-                    .get("hue")
-                    .map(|hue| {
-                        response_map
-                            .get("sat")
-                            .map(|sat| {
-                                hue.parse::<u16>().ok().map(|hue| {
-                                    sat.parse::<u8>().ok().map(|sat| {
-                                        LightMode::Hsv(HSV {
-                                            hue: hue,
-                                            saturation: sat,
-                                        })
+                    .and_then(|ct| ct.parse::<u16>().ok())
+                    .map(LightMode::ColorTemperature),
+                3 => response_map.get("hue").and_then(|hue| {
+                    response_map
+                        .get("sat")
+                        .and_then(|sat| {
+                            hue.parse::<u16>().ok().map(|hue| {
+                                sat.parse::<u8>().ok().map(|sat| {
+                                    LightMode::Hsv(HSV {
+                                        hue,
+                                        saturation: sat,
                                     })
                                 })
                             })
-                            .flatten()
-                            .flatten()
-                    })
-                    .flatten(),
+                        })
+                        .flatten()
+                }),
                 _ => None,
             })
-            .flatten()
     }
 }
@@ -1,7 +1,7 @@
 use enum_iterator::IntoEnumIterator;
-use std::convert::TryFrom;
+use std::{convert::TryFrom, fmt};
 
-#[derive(Debug, Hash, PartialEq, Eq, IntoEnumIterator)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, IntoEnumIterator)]
 pub enum Method {
     GetProp,
     SetPower,
@@ -40,20 +40,45 @@ pub enum Method {
     DevToggle,
 }
 
+impl Method {
+    /// Whether sending the method a second time leaves the bulb in the same
+    /// state as sending it once. Relative changes like toggles and
+    /// adjustments are not, neither is adding a timer.
+    pub fn is_idempotent(&self) -> bool {
+        !matches!(
+            self,
+            Method::Toggle
+                | Method::BgToggle
+                | Method::DevToggle
+                | Method::SetAdjust
+                | Method::BgSetAdjust
+                | Method::AdjustBright
+                | Method::AdjustCt
+                | Method::AdjustColor
+                | Method::BgAdjustBright
+                | Method::BgAdjustCt
+                | Method::BgAdjustColor
+                | Method::CronAdd
+        )
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name: &str = self.into();
+        write!(f, "{}", name)
+    }
+}
+
 impl TryFrom<&str> for Method {
     type Error = &'static str;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         let err: &'static str = "Doesn't match known methods.";
         Method::into_enum_iter()
-            .find_map(|m| {
-                let method_str: &str = (&m).into();
-
-                if method_str == value {
-                    Some(m)
-                } else {
-                    None
-                }
+            .find(|m| {
+                let method_str: &str = m.into();
+                method_str == value
             })
             .ok_or(err)
     }
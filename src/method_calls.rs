@@ -1,45 +1,61 @@
+use serde::{Deserialize, Serialize};
 use std::{
-    convert::TryInto,
+    convert::{TryFrom, TryInto},
     io::{self, Read, Write},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use rand::{Rng, RngCore};
-
 use crate::{
+    bulb::Bulb,
     connection::{
-        AdjustAction, AdjustableProp, Brightness, BulbConnection, CfAction, ColorFlow, Cron,
-        CronResponse, CronType, Ct, ErrorResponse, FlowTuple, FlowTupleMode, MethodCallError,
-        MethodCallResponse, MusicMode, PowerMode, Scene, StringVecResponse, TransitionMode, CT_MAX,
-        CT_MIN, MAX_BRIGHTNESS, MINIMUM_CF_DURATION, MINIMUM_TRANSITION_DURATION,
-        MIN_AUTO_DELAY_OFF_MINUTES,
+        is_timeout, AdjustAction, AdjustableProp, BulbConnection, CfAction, ColorFlow, Cron,
+        CronResponse, CronType, ErrorResponse, FlowTuple, FlowTupleMode, MethodCallError,
+        MethodCallResponse, MusicMode, Notification, PowerMode, ReadTimeoutSetter, Scene,
+        StringVecResponse, TransitionMode, MAX_BRIGHTNESS, MINIMUM_CF_DURATION,
+        MINIMUM_TRANSITION_DURATION, MIN_AUTO_DELAY_OFF_MINUTES,
     },
-    lightmode::HSV,
+    frame::FrameBuffer,
+    light_methods::{control_methods, light_methods},
+    lightmode::{LightMode, HSV},
     method::Method,
+    model::Capabilities,
+    name::{decode_name, encode_name},
     power::Power,
     rgb::RGB,
 };
 
-enum MethodArg {
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum MethodArg {
     String(String),
     Int(i32),
 }
 
+/// A request as it goes over the wire,
+/// `{"id":1,"method":"set_name","params":["Living \"Room\""]}`.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Request<'a> {
+    pub(crate) id: i16,
+    pub(crate) method: &'a str,
+    pub(crate) params: Vec<MethodArg>,
+}
+
 impl FlowTuple {
-    fn to_expression(&self) -> Result<Vec<u32>, MethodCallError> {
+    fn to_expression(&self, light: &Capabilities) -> Result<Vec<u32>, MethodCallError> {
         if self.duration < MINIMUM_CF_DURATION {
             return Err(MethodCallError::BadRequest);
         }
 
         let (second_arg, third_arg, fourth_arg) = match &self.mode {
             FlowTupleMode::Color(c) => {
+                check_color(light)?;
                 if c.brightness > MAX_BRIGHTNESS {
                     return Err(MethodCallError::BadRequest);
                 }
                 (1, u32::from(&c.color), c.brightness as u32)
             }
             FlowTupleMode::Ct(ct) => {
-                if ct.brightness > MAX_BRIGHTNESS {
+                if ct.brightness > MAX_BRIGHTNESS || !light.ct_range().contains(&ct.ct) {
                     return Err(MethodCallError::BadRequest);
                 }
                 (2, ct.ct as u32, ct.brightness as u32)
@@ -47,12 +63,12 @@ impl FlowTuple {
             FlowTupleMode::Sleep => (7, u32::MIN, u32::MIN),
         };
 
-        return Ok(vec![
+        Ok(vec![
             self.duration.as_millis() as u32,
             second_arg,
             third_arg,
             fourth_arg,
-        ]);
+        ])
     }
 }
 
@@ -67,11 +83,13 @@ impl CfAction {
 }
 
 impl ColorFlow {
-    fn params(&self) -> Result<Vec<MethodArg>, MethodCallError> {
+    /// Checks each step against what `light` takes as well, since a flow is
+    /// built without knowing the light it ends up on.
+    pub(crate) fn params(&self, light: &Capabilities) -> Result<Vec<MethodArg>, MethodCallError> {
         let mut flow_vec: Vec<String> = Vec::with_capacity(4 * self.sequence.len());
 
         for tuple in &self.sequence {
-            let expr = tuple.to_expression()?;
+            let expr = tuple.to_expression(light)?;
             for ex in expr {
                 flow_vec.push(ex.to_string());
             }
@@ -96,8 +114,12 @@ impl<'a, 'b> Scene<'a, 'b> {
         }
     }
 
-    fn params(&self) -> Result<Vec<MethodArg>, MethodCallError> {
+    pub(crate) fn params(&self, light: &Capabilities) -> Result<Vec<MethodArg>, MethodCallError> {
         match self {
+            Scene::Color(_, _) | Scene::HSV(_, _) if !light.color => {
+                Err(MethodCallError::UnsupportedMethod)
+            }
+            Scene::Ct(ct, _) if !light.ct_range().contains(ct) => Err(MethodCallError::BadRequest),
             Scene::Color(rgb, brightness) => Ok(vec![
                 MethodArg::String(self.val().to_string()),
                 MethodArg::Int(u32::from(*rgb) as i32),
@@ -114,7 +136,7 @@ impl<'a, 'b> Scene<'a, 'b> {
                 MethodArg::Int(*ct as i32),
                 MethodArg::Int(*brightness as i32),
             ]),
-            Scene::Cf(cf) => cf.params().map(|p| {
+            Scene::Cf(cf) => cf.params(light).map(|p| {
                 let mut args = vec![MethodArg::String(self.val().to_string())];
 
                 for param in p {
@@ -143,14 +165,14 @@ impl<'a, 'b> Scene<'a, 'b> {
 }
 
 impl TransitionMode {
-    fn to_method_args(&self) -> Result<Vec<MethodArg>, MethodCallError> {
+    fn to_method_args(self) -> Result<Vec<MethodArg>, MethodCallError> {
         match self {
             TransitionMode::Sudden => Ok(vec![
                 MethodArg::String("sudden".to_string()),
                 MethodArg::Int(50),
             ]),
             TransitionMode::Smooth(d) => {
-                if d < &MINIMUM_TRANSITION_DURATION {
+                if d < MINIMUM_TRANSITION_DURATION {
                     return Err(MethodCallError::BadRequest);
                 }
 
@@ -168,557 +190,563 @@ impl TransitionMode {
     }
 }
 
-impl MethodArg {
-    fn to_str(&self) -> String {
-        match self {
-            MethodArg::String(str) => {
-                let mut string = String::new();
-                string.push_str("\"");
-                string.push_str(str);
-                string.push_str("\"");
-                string
-            }
-            MethodArg::Int(int) => int.to_string(),
-        }
+pub(crate) fn get_prop_args(props: &[&str]) -> Result<Vec<MethodArg>, MethodCallError> {
+    if props.is_empty() {
+        return Err(MethodCallError::BadRequest);
     }
-}
-
-impl<C: Read + Write, R: RngCore> BulbConnection<C, R> {
-    fn call_method<T>(&mut self, method: Method, args: Vec<MethodArg>) -> Result<T, MethodCallError>
-    where
-        for<'a> T: MethodCallResponse<'a>,
-    {
-        if !self.bulb.support.contains(&method) {
-            return Err(MethodCallError::UnsupportedMethod);
-        }
-
-        let mut conn = self
-            .connection
-            .lock()
-            .map_err(|_| MethodCallError::SynchronizationError)?;
 
-        let id: i16 = self.rng.gen();
-        let message = create_message(id, &method, args);
-
-        conn.write(message.as_bytes())
-            .map_err(|err| MethodCallError::IOError(err))?;
-
-        let mut buf = [0; 2048];
-        conn.read(&mut buf)
-            .map_err(|err| MethodCallError::IOError(err))?;
-
-        let rs = std::str::from_utf8(&buf)
-            .map_err(|_| MethodCallError::ParseError)
-            .map(|s| s.trim_end_matches(char::from(0)).trim_end())
-            .map(|s| {
-                serde_json::from_str::<T>(s).map_err(|_| {
-                    let error = serde_json::from_str::<ErrorResponse>(s);
-                    match error {
-                        Ok(ers) => MethodCallError::ErrorResponse(ers),
-                        Err(_) => MethodCallError::ParseError,
-                    }
-                })
-            })??;
+    Ok(props
+        .iter()
+        .map(|p| MethodArg::String(p.to_string()))
+        .collect())
+}
 
-        if rs.id() == id {
-            Ok(rs)
-        } else {
-            Err(MethodCallError::SynchronizationError)
+/// Decodes the `name` values in a `get_prop` reply, see [`decode_name`].
+/// `props` are the names requested, in order.
+pub(crate) fn decode_names<'a>(
+    props: impl IntoIterator<Item = &'a str>,
+    response: &mut StringVecResponse,
+) {
+    for (prop, value) in props.into_iter().zip(response.result.iter_mut()) {
+        if prop == "name" {
+            *value = decode_name(value);
         }
     }
+}
 
-    /// This method is used to retrieve current property of smart LED.
-    /// The parameter is a list of property names and the response contains a
-    /// list of corresponding property values. If the requested property name is not recognized by
-    /// smart LED, then a empty string value ("") will be returned.
-    pub fn get_prop(&mut self, props: &[&str]) -> Result<StringVecResponse, MethodCallError> {
-        if props.is_empty() {
-            return Err(MethodCallError::BadRequest);
-        }
-
-        let args = props
-            .iter()
-            .map(|p| MethodArg::String(p.to_string()))
-            .collect();
-
-        self.call_method(Method::GetProp, args)
+/// Fails with `UnsupportedMethod` unless `light` takes colours.
+fn check_color(light: &Capabilities) -> Result<(), MethodCallError> {
+    if light.color {
+        Ok(())
+    } else {
+        Err(MethodCallError::UnsupportedMethod)
     }
+}
 
-    /// This method is used to change the color temperature of a smart LED.
-    /// "ct_value" is the target color temperature. The type is integer and
-    /// range is 1700 ~ 6500 (k).
-    /// Smooth transition duration in milliseconds should be between 30 and i32::MAX.
-    pub fn set_ct_abx(
-        &mut self,
-        ct_value: u16,
-        mode: TransitionMode,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        if ct_value > CT_MAX || ct_value < CT_MIN {
-            return Err(MethodCallError::BadRequest);
-        }
-
-        let args = mode.to_method_args()?;
-
-        self.call_method(
-            Method::SetCtAbx,
-            vec![MethodArg::Int(ct_value.into())]
-                .into_iter()
-                .chain(args.into_iter())
-                .collect(),
-        )
+/// `light` is the main light's [`Bulb::capabilities`] or [`BACKGROUND`], as
+/// in the other argument builders taking one.
+pub(crate) fn ct_abx_args(
+    ct_value: u16,
+    light: &Capabilities,
+    mode: TransitionMode,
+) -> Result<Vec<MethodArg>, MethodCallError> {
+    if !light.ct_range().contains(&ct_value) {
+        return Err(MethodCallError::BadRequest);
     }
 
-    pub fn set_rgb(
-        &mut self,
-        rgb: &RGB,
-        mode: TransitionMode,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        let args = mode.to_method_args()?;
+    let args = mode.to_method_args()?;
 
-        self.call_method(
-            Method::SetRgb,
-            vec![MethodArg::Int(u32::from(rgb) as i32)]
-                .into_iter()
-                .chain(args.into_iter())
-                .collect(),
-        )
-    }
+    Ok(vec![MethodArg::Int(ct_value.into())]
+        .into_iter()
+        .chain(args)
+        .collect())
+}
 
-    pub fn set_hsv(
-        &mut self,
-        hsv: &HSV,
-        mode: TransitionMode,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        if !hsv.validate() {
-            return Err(MethodCallError::BadRequest);
-        }
+pub(crate) fn rgb_args(
+    rgb: &RGB,
+    light: &Capabilities,
+    mode: TransitionMode,
+) -> Result<Vec<MethodArg>, MethodCallError> {
+    check_color(light)?;
+    let args = mode.to_method_args()?;
+
+    Ok(vec![MethodArg::Int(u32::from(rgb) as i32)]
+        .into_iter()
+        .chain(args)
+        .collect())
+}
 
-        let args = mode.to_method_args()?;
+pub(crate) fn hsv_args(
+    hsv: &HSV,
+    light: &Capabilities,
+    mode: TransitionMode,
+) -> Result<Vec<MethodArg>, MethodCallError> {
+    check_color(light)?;
+    if !hsv.validate() {
+        return Err(MethodCallError::BadRequest);
+    }
+
+    let args = mode.to_method_args()?;
+
+    Ok(vec![
+        MethodArg::Int(hsv.hue as i32),
+        MethodArg::Int(hsv.saturation as i32),
+    ]
+    .into_iter()
+    .chain(args)
+    .collect())
+}
 
-        self.call_method(
-            Method::SetHsv,
-            vec![
-                MethodArg::Int(hsv.hue as i32),
-                MethodArg::Int(hsv.saturation as i32),
-            ]
-            .into_iter()
-            .chain(args.into_iter())
-            .collect(),
-        )
+pub(crate) fn bright_args(
+    brightness: u8,
+    mode: TransitionMode,
+) -> Result<Vec<MethodArg>, MethodCallError> {
+    if brightness > MAX_BRIGHTNESS {
+        return Err(MethodCallError::BadRequest);
     }
 
-    pub fn set_bright(
-        &mut self,
-        brightness: u8,
-        mode: TransitionMode,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        if brightness > MAX_BRIGHTNESS {
-            return Err(MethodCallError::BadRequest);
-        }
+    let args = mode.to_method_args()?;
 
-        let args = mode.to_method_args()?;
-        self.call_method(
-            Method::SetBright,
-            vec![MethodArg::Int(brightness as i32)]
-                .into_iter()
-                .chain(args.into_iter())
-                .collect(),
-        )
-    }
+    Ok(vec![MethodArg::Int(brightness as i32)]
+        .into_iter()
+        .chain(args)
+        .collect())
+}
 
-    pub fn set_power(
-        &mut self,
-        power: Power,
-        trans_mode: TransitionMode,
-        power_mode: Option<PowerMode>,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        let args = trans_mode.to_method_args()?;
-
-        let mut args: Vec<MethodArg> = vec![MethodArg::String(power.into())]
-            .into_iter()
-            .chain(args.into_iter())
-            .collect();
-
-        if let Some(pm) = power_mode {
-            args.push(MethodArg::Int(pm as i32));
+pub(crate) fn power_args(
+    power: Power,
+    light: &Capabilities,
+    trans_mode: TransitionMode,
+    power_mode: Option<PowerMode>,
+) -> Result<Vec<MethodArg>, MethodCallError> {
+    match power_mode {
+        Some(PowerMode::Rgb) | Some(PowerMode::Hsv) => check_color(light)?,
+        Some(PowerMode::NightLight) if !light.nightlight => {
+            return Err(MethodCallError::UnsupportedMethod)
         }
-
-        self.call_method(Method::SetPower, args)
+        _ => {}
     }
+    let args = trans_mode.to_method_args()?;
 
-    pub fn toggle(&mut self) -> Result<StringVecResponse, MethodCallError> {
-        self.call_method(Method::Toggle, vec![])
-    }
+    let mut args: Vec<MethodArg> = vec![MethodArg::String(power.into())]
+        .into_iter()
+        .chain(args)
+        .collect();
 
-    pub fn set_default(&mut self) -> Result<StringVecResponse, MethodCallError> {
-        self.call_method(Method::SetDefault, vec![])
+    if let Some(pm) = power_mode {
+        args.push(MethodArg::Int(pm as i32));
     }
 
-    pub fn start_cf(&mut self, cf: &ColorFlow) -> Result<StringVecResponse, MethodCallError> {
-        cf.params()
-            .and_then(|p| self.call_method(Method::StartCf, p))
-    }
+    Ok(args)
+}
 
-    pub fn stop_cf(&mut self) -> Result<StringVecResponse, MethodCallError> {
-        self.call_method(Method::StopCf, vec![])
-    }
+pub(crate) fn cron_add_args(cron: &Cron) -> Vec<MethodArg> {
+    vec![MethodArg::Int(0), MethodArg::Int(cron.minutes as i32)]
+}
 
-    pub fn set_scene(&mut self, scene: &Scene) -> Result<StringVecResponse, MethodCallError> {
-        scene
-            .params()
-            .and_then(|p| self.call_method(Method::SetScene, p))
-    }
+pub(crate) fn cron_type_args(_cron_type: &CronType) -> Vec<MethodArg> {
+    vec![MethodArg::Int(0)]
+}
 
-    /// Usage: This method is used to start a timer job on the smart LED.
-    pub fn cron_add(&mut self, cron: &Cron) -> Result<StringVecResponse, MethodCallError> {
-        self.call_method(
-            Method::CronAdd,
-            vec![MethodArg::Int(0), MethodArg::Int(cron.minutes as i32)],
-        )
-    }
+pub(crate) fn set_adjust_args(prop: &AdjustableProp, action: &AdjustAction) -> Vec<MethodArg> {
+    let action_str: &str = action.into();
+    let prop_str: &str = prop.into();
+    vec![
+        MethodArg::String(action_str.to_string()),
+        MethodArg::String(prop_str.to_string()),
+    ]
+}
 
-    pub fn cron_get(&mut self, cron_type: &CronType) -> Result<CronResponse, MethodCallError> {
-        self.call_method(Method::CronGet, vec![MethodArg::Int(0)])
+pub(crate) fn music_args(mode: MusicMode) -> Vec<MethodArg> {
+    match mode {
+        MusicMode::On(ip_address, port) => vec![
+            MethodArg::Int(1),
+            MethodArg::String(ip_address.to_string()),
+            MethodArg::Int(port as i32),
+        ],
+        MusicMode::Off => vec![MethodArg::Int(0)],
     }
+}
 
-    pub fn cron_del(&mut self, cron_type: &CronType) -> Result<StringVecResponse, MethodCallError> {
-        self.call_method(Method::CronDel, vec![MethodArg::Int(0)])
-    }
+pub(crate) fn name_args(name: &str) -> Result<Vec<MethodArg>, MethodCallError> {
+    let stored = encode_name(name).ok_or(MethodCallError::BadRequest)?;
+    Ok(vec![MethodArg::String(stored)])
+}
 
-    pub fn set_adjust(
-        &mut self,
-        prop: &AdjustableProp,
-        action: &AdjustAction,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        let action_str: &str = action.into();
-        let prop_str: &str = prop.into();
-        self.call_method(
-            Method::SetAdjust,
-            vec![
-                MethodArg::String(action_str.to_string()),
-                MethodArg::String(prop_str.to_string()),
-            ],
-        )
-    }
+/// Applies what a successful call changed to the cached bulb state. Calls
+/// whose outcome depends on state we don't track, like adjustments and
+/// flows, are left to the `props` notification that follows them.
+pub(crate) fn apply_call(bulb: &mut Bulb, method: Method, args: &[MethodArg]) {
+    use MethodArg::{Int, String as Str};
 
-    pub fn set_music(&mut self, mode: MusicMode) -> Result<StringVecResponse, MethodCallError> {
-        let method = Method::SetMusic;
-        match mode {
-            MusicMode::On(ip_address, port) => self.call_method(
-                method,
-                vec![
-                    MethodArg::Int(1),
-                    MethodArg::String(ip_address.to_string()),
-                    MethodArg::Int(port as i32),
-                ],
-            ),
-            MusicMode::Off => self.call_method(method, vec![MethodArg::Int(0)]),
+    match (method, args) {
+        (Method::SetPower, [Str(power), ..]) => {
+            if let Ok(power) = Power::try_from(power) {
+                bulb.power = power;
+            }
         }
-    }
-
-    pub fn set_name(&mut self, name: &str) -> Result<StringVecResponse, MethodCallError> {
-        self.call_method(Method::SetName, vec![MethodArg::String(name.to_string())])
-    }
-
-    pub fn bg_set_ct_abx(
-        &mut self,
-        ct_value: u16,
-        mode: TransitionMode,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        if ct_value > CT_MAX || ct_value < CT_MIN {
-            return Err(MethodCallError::BadRequest);
+        (Method::Toggle, _) => {
+            bulb.power = match bulb.power {
+                Power::On => Power::Off,
+                Power::Off => Power::On,
+            }
         }
-
-        let args = mode.to_method_args()?;
-
-        self.call_method(
-            Method::BgSetCtAbx,
-            vec![MethodArg::Int(ct_value.into())]
-                .into_iter()
-                .chain(args.into_iter())
-                .collect(),
-        )
-    }
-
-    pub fn bg_set_rgb(
-        &mut self,
-        rgb: &RGB,
-        mode: TransitionMode,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        let args = mode.to_method_args()?;
-
-        self.call_method(
-            Method::BgSetRgb,
-            vec![MethodArg::Int(u32::from(rgb) as i32)]
-                .into_iter()
-                .chain(args.into_iter())
-                .collect(),
-        )
-    }
-
-    pub fn bg_set_hsv(
-        &mut self,
-        hsv: &HSV,
-        mode: TransitionMode,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        if !hsv.validate() {
-            return Err(MethodCallError::BadRequest);
+        (Method::SetBright, [Int(bright), ..]) => bulb.bright = *bright as u8,
+        (Method::SetRgb, [Int(rgb), ..]) => {
+            bulb.color_mode = LightMode::Color(RGB::from(*rgb as u32))
         }
-
-        let args = mode.to_method_args()?;
-
-        self.call_method(
-            Method::BgSetHsv,
-            vec![
-                MethodArg::Int(hsv.hue as i32),
-                MethodArg::Int(hsv.saturation as i32),
-            ]
-            .into_iter()
-            .chain(args.into_iter())
-            .collect(),
-        )
-    }
-
-    pub fn bg_set_bright(
-        &mut self,
-        brightness: u8,
-        mode: TransitionMode,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        if brightness > MAX_BRIGHTNESS {
-            return Err(MethodCallError::BadRequest);
+        (Method::SetHsv, [Int(hue), Int(sat), ..]) => {
+            bulb.color_mode = LightMode::Hsv(HSV {
+                hue: *hue as u16,
+                saturation: *sat as u8,
+            })
         }
-
-        let args = mode.to_method_args()?;
-        self.call_method(
-            Method::BgSetBright,
-            vec![MethodArg::Int(brightness as i32)]
-                .into_iter()
-                .chain(args.into_iter())
-                .collect(),
-        )
-    }
-
-    pub fn bg_set_power(
-        &mut self,
-        power: Power,
-        trans_mode: TransitionMode,
-        power_mode: Option<PowerMode>,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        let args = trans_mode.to_method_args()?;
-
-        let mut args: Vec<MethodArg> = vec![MethodArg::String(power.into())]
-            .into_iter()
-            .chain(args.into_iter())
-            .collect();
-
-        if let Some(pm) = power_mode {
-            args.push(MethodArg::Int(pm as i32));
+        (Method::SetCtAbx, [Int(ct), ..]) => {
+            bulb.color_mode = LightMode::ColorTemperature(*ct as u16)
         }
-
-        self.call_method(Method::BgSetPower, args)
+        (Method::SetName, [Str(name)]) => bulb.name = decode_name(name),
+        (Method::SetScene, [Str(scene), values @ ..]) => {
+            let bright = match (scene.as_str(), values) {
+                ("color", [Int(rgb), Int(bright)]) => {
+                    bulb.color_mode = LightMode::Color(RGB::from(*rgb as u32));
+                    bright
+                }
+                ("hsv", [Int(hue), Int(sat), Int(bright)]) => {
+                    bulb.color_mode = LightMode::Hsv(HSV {
+                        hue: *hue as u16,
+                        saturation: *sat as u8,
+                    });
+                    bright
+                }
+                ("ct", [Int(ct), Int(bright)]) => {
+                    bulb.color_mode = LightMode::ColorTemperature(*ct as u16);
+                    bright
+                }
+                ("auto_delay_off", [Int(bright), _]) => bright,
+                _ => return,
+            };
+            bulb.power = Power::On;
+            bulb.bright = *bright as u8;
+        }
+        _ => {}
     }
+}
 
-    pub fn bg_toggle(&mut self) -> Result<StringVecResponse, MethodCallError> {
-        self.call_method(Method::BgToggle, vec![])
+pub(crate) fn adjust_args(
+    percentage: i8,
+    duration: &Duration,
+) -> Result<Vec<MethodArg>, MethodCallError> {
+    if !(-100..=100).contains(&percentage) {
+        return Err(MethodCallError::BadRequest);
     }
-
-    pub fn bg_set_default(&mut self) -> Result<StringVecResponse, MethodCallError> {
-        self.call_method(Method::BgSetDefault, vec![])
+    if duration < &MINIMUM_TRANSITION_DURATION {
+        return Err(MethodCallError::BadRequest);
     }
+    Ok(vec![
+        MethodArg::Int(percentage as i32),
+        MethodArg::Int(duration.as_millis() as i32),
+    ])
+}
 
-    pub fn bg_start_cf(&mut self, cf: &ColorFlow) -> Result<StringVecResponse, MethodCallError> {
-        cf.params()
-            .and_then(|p| self.call_method(Method::BgStartCf, p))
+/// Pops complete frames off `frames` until the reply shows up, handing
+/// notifications to `notify` on the way. Returns `None` when more data has
+/// to be read first.
+pub(crate) fn next_reply(
+    frames: &mut FrameBuffer,
+    mut notify: impl FnMut(Notification),
+) -> Result<Option<String>, MethodCallError> {
+    while let Some(frame) = frames.next_frame() {
+        let frame = frame.map_err(|_| MethodCallError::ParseError)?;
+        match Notification::parse(&frame) {
+            Some(notification) => notify(notification),
+            None => return Ok(Some(frame)),
+        }
     }
+    Ok(None)
+}
 
-    pub fn bg_stop_cf(&mut self) -> Result<StringVecResponse, MethodCallError> {
-        self.call_method(Method::BgStopCf, vec![])
-    }
+#[derive(Deserialize)]
+struct ReplyId {
+    id: i16,
+}
 
-    pub fn bg_set_scene(&mut self, scene: &Scene) -> Result<StringVecResponse, MethodCallError> {
-        scene
-            .params()
-            .and_then(|p| self.call_method(Method::BgSetScene, p))
-    }
+/// Reads just the id of a reply frame, so it can be matched to its request.
+pub(crate) fn reply_id(reply: &str) -> Option<i16> {
+    serde_json::from_str::<ReplyId>(reply).ok().map(|r| r.id)
+}
 
-    pub fn bg_set_adjust(
-        &mut self,
-        prop: &AdjustableProp,
-        action: &AdjustAction,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        let action_str: &str = action.into();
-        let prop_str: &str = prop.into();
-        self.call_method(
-            Method::BgSetAdjust,
-            vec![
-                MethodArg::String(action_str.to_string()),
-                MethodArg::String(prop_str.to_string()),
-            ],
-        )
-    }
+/// Parses a single reply frame, falling back to the error response shape
+/// when the reply doesn't match `T`.
+pub(crate) fn parse_response<T>(reply: &str, id: i16) -> Result<T, MethodCallError>
+where
+    for<'a> T: MethodCallResponse<'a>,
+{
+    let rs = serde_json::from_str::<T>(reply).map_err(|_| {
+        let error = serde_json::from_str::<ErrorResponse>(reply);
+        match error {
+            Ok(ers) => MethodCallError::ErrorResponse(ers),
+            Err(_) => MethodCallError::ParseError,
+        }
+    })?;
 
-    pub fn dev_toggle(&mut self) -> Result<StringVecResponse, MethodCallError> {
-        self.call_method(Method::DevToggle, vec![])
+    if rs.id() == id {
+        Ok(rs)
+    } else {
+        Err(MethodCallError::SynchronizationError)
     }
+}
 
-    pub fn adjust_bright(
-        &mut self,
-        percentage: i8,
-        duration: &Duration,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        self.adjust(percentage, duration, Method::AdjustBright)
+/// Reads the next chunk of the stream into `frames`. With a deadline, the
+/// read is cut short when it passes, provided the stream can take a read
+/// timeout.
+pub(crate) fn read_frames<C: Read>(
+    conn: &mut C,
+    frames: &mut FrameBuffer,
+    deadline: Option<Instant>,
+    set_read_timeout: Option<ReadTimeoutSetter<C>>,
+) -> Result<(), MethodCallError> {
+    if let Some(deadline) = deadline {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or(MethodCallError::Timeout)?;
+        if let Some(set_read_timeout) = set_read_timeout {
+            set_read_timeout(conn, Some(remaining)).map_err(MethodCallError::IOError)?;
+        }
     }
 
-    pub fn adjust_ct(
-        &mut self,
-        percentage: i8,
-        duration: &Duration,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        self.adjust(percentage, duration, Method::AdjustCt)
+    let mut buf = [0; 2048];
+    let read = conn.read(&mut buf).map_err(io_error)?;
+    if read == 0 {
+        return Err(MethodCallError::IOError(
+            io::ErrorKind::UnexpectedEof.into(),
+        ));
     }
+    frames.extend(&buf[..read]);
+    Ok(())
+}
 
-    pub fn adjust_color(
-        &mut self,
-        percentage: i8,
-        duration: &Duration,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        self.adjust(percentage, duration, Method::AdjustColor)
+/// Tells a read or write that ran out of time apart from a broken stream.
+pub(crate) fn io_error(err: io::Error) -> MethodCallError {
+    if is_timeout(&err) {
+        MethodCallError::Timeout
+    } else {
+        MethodCallError::IOError(err)
     }
+}
 
-    pub fn bg_adjust_bright(
+impl<C: Read + Write> BulbConnection<C> {
+    pub(crate) fn call_method<T>(
         &mut self,
-        percentage: i8,
-        duration: &Duration,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        self.adjust(percentage, duration, Method::BgAdjustBright)
+        method: Method,
+        args: Result<Vec<MethodArg>, MethodCallError>,
+    ) -> Result<T, MethodCallError>
+    where
+        for<'a> T: MethodCallResponse<'a>,
+    {
+        self.checked_call(method, args)
+            .map_err(|err| err.in_call(method, &self.bulb))
     }
 
-    pub fn bg_adjust_ct(
+    fn checked_call<T>(
         &mut self,
-        percentage: i8,
-        duration: &Duration,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        self.adjust(percentage, duration, Method::BgAdjustCt)
-    }
+        method: Method,
+        args: Result<Vec<MethodArg>, MethodCallError>,
+    ) -> Result<T, MethodCallError>
+    where
+        for<'a> T: MethodCallResponse<'a>,
+    {
+        let args = args?;
+        if !self.bulb.support.contains(&method) {
+            return Err(MethodCallError::UnsupportedMethod);
+        }
 
-    pub fn bg_adjust_color(
-        &mut self,
-        percentage: i8,
-        duration: &Duration,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        self.adjust(percentage, duration, Method::BgAdjustColor)
+        // Music mode lifts the bulb's quota, so nothing is held back or
+        // counted against it.
+        if self.music_mode {
+            return self.send(method, args);
+        }
+        let args = self.admit(method, args)?;
+        let result = self.send(method, args);
+        let _ = self.send_deferred(false);
+        result
     }
 
-    fn adjust(
+    /// Sends a call that has been validated and let through the rate limit.
+    pub(crate) fn send<T>(
         &mut self,
-        percentage: i8,
-        duration: &Duration,
         method: Method,
-    ) -> Result<StringVecResponse, MethodCallError> {
-        if percentage < -100 || percentage > 100 {
-            return Err(MethodCallError::BadRequest);
+        args: Vec<MethodArg>,
+    ) -> Result<T, MethodCallError>
+    where
+        for<'a> T: MethodCallResponse<'a>,
+    {
+        if self.is_broken() {
+            self.reconnect()?;
         }
-        if duration < &MINIMUM_TRANSITION_DURATION {
-            return Err(MethodCallError::BadRequest);
+
+        let id = self.ids.next_id();
+        let message = create_message(id, &method, args.clone());
+
+        let result = match self.exchange(id, &message) {
+            Err(MethodCallError::IOError(err)) if self.can_reconnect() => {
+                self.reconnect()?;
+                if method.is_idempotent() {
+                    self.exchange(id, &message)
+                } else {
+                    Err(MethodCallError::IOError(err))
+                }
+            }
+            result => result,
+        };
+        if result.is_ok() {
+            apply_call(&mut self.bulb, method, &args);
         }
-        self.call_method(
-            method,
-            vec![
-                MethodArg::Int(percentage as i32),
-                MethodArg::Int(duration.as_millis() as i32),
-            ],
-        )
+        result
     }
-}
 
-struct MockTcpConnection {
-    when_written: String,
-    return_val: String,
-    written_val: Option<String>,
-}
+    /// Writes one request and reads until its reply arrives.
+    fn exchange<T>(&mut self, id: i16, message: &str) -> Result<T, MethodCallError>
+    where
+        for<'a> T: MethodCallResponse<'a>,
+    {
+        let mut conn = self
+            .connection
+            .lock()
+            .map_err(|_| MethodCallError::SynchronizationError)?;
 
-impl Read for MockTcpConnection {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self
-            .written_val
-            .clone()
-            .unwrap()
-            .trim()
-            .eq(self.when_written.trim())
-        {
-            let bytes = self.return_val.as_bytes();
-
-            for (i, elem) in buf.iter_mut().enumerate() {
-                if i >= bytes.len() {
-                    break;
+        conn.write_all(message.as_bytes()).map_err(io_error)?;
+
+        let deadline = self
+            .response_timeout
+            .map(|timeout| Instant::now() + timeout);
+        loop {
+            let (bulb, subscribers) = (&mut self.bulb, &mut self.subscribers);
+            if let Some(reply) = next_reply(&mut self.frames, |n| subscribers.deliver(bulb, n))? {
+                match reply_id(&reply) {
+                    // A late reply to an earlier request, not ours to return.
+                    Some(reply_id) if reply_id != id => continue,
+                    _ => return parse_response(&reply, id),
                 }
-                *elem = bytes[i];
             }
 
-            return io::Result::Ok(usize::min(bytes.len(), buf.len()));
+            read_frames(
+                &mut *conn,
+                &mut self.frames,
+                deadline,
+                self.read_timeout_setter,
+            )?;
         }
-        return io::Result::Ok(0);
     }
-}
 
-impl Write for MockTcpConnection {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let mut str = String::new();
-        let _ = buf.clone().read_to_string(&mut str);
-        self.written_val = Some(str);
-        println!("mock written: {}", self.written_val.as_ref().unwrap());
-        println!("when written: {}", self.when_written);
-        io::Result::Ok(buf.len())
+    /// This method is used to retrieve current property of smart LED.
+    /// The parameter is a list of property names and the response contains a
+    /// list of corresponding property values. If the requested property name is not recognized by
+    /// smart LED, then a empty string value ("") will be returned.
+    /// The `name` value comes back decoded with [`decode_name`].
+    pub fn get_prop(&mut self, props: &[&str]) -> Result<StringVecResponse, MethodCallError> {
+        let mut response = self.call_method(Method::GetProp, get_prop_args(props))?;
+        decode_names(props.iter().copied(), &mut response);
+        Ok(response)
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        io::Result::Ok(())
+    pub fn cron_get(&mut self, cron_type: &CronType) -> Result<CronResponse, MethodCallError> {
+        self.call_method(Method::CronGet, Ok(cron_type_args(cron_type)))
+    }
+
+    pub fn set_music(&mut self, mode: MusicMode) -> Result<StringVecResponse, MethodCallError> {
+        let on = matches!(mode, MusicMode::On(..));
+        let result = self.call_method(Method::SetMusic, Ok(music_args(mode)))?;
+        self.music_mode = on;
+        Ok(result)
     }
+
+    light_methods!(call_method -> Result<StringVecResponse, MethodCallError>, bulb);
+    control_methods!(call_method -> Result<StringVecResponse, MethodCallError>, bulb);
 }
 
-macro_rules! set {
-    ( $( $x:expr ),* ) => {  // Match zero or more comma delimited items
-        {
-            let mut temp_set = std::collections::HashSet::new();  // Create a mutable HashSet
-            $(
-                temp_set.insert($x); // Insert each item matched into the HashSet
-            )*
-            temp_set // Return the populated HashSet
-        }
+pub(crate) fn create_message(id: i16, method: &Method, args: Vec<MethodArg>) -> String {
+    let request = Request {
+        id,
+        method: method.into(),
+        params: args,
     };
+    // Strings and i32s always serialize, there is nothing to fail on.
+    let mut message = serde_json::to_string(&request).expect("request serializes to JSON");
+    message.push_str("\r\n");
+    message
 }
 
-const TEST_OK_VAL: &str = "{\"id\":1, \"result\":[\"ok\"]}";
-
+#[cfg(test)]
 mod tests {
-    use std::{result, sync::Mutex, time::Duration};
-
-    use rand::rngs::mock::{self, StepRng};
+    use std::{
+        io::{self, BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
 
     use crate::{
         bulb::Bulb,
         connection::{
-            BulbConnection, ColorFlow, ColorFlowTupleMode, CtFlowTupleMode, FlowTuple,
-            FlowTupleMode,
+            BulbConnection, BulbErrorKind, BulbErrorResponse, ColorFlow, ColorFlowTupleMode,
+            ConnectionOptions, CtFlowTupleMode, FlowTuple, FlowTupleMode, TcpConnection,
         },
         lightmode::{LightMode, HSV},
         method::Method,
+        power::Power,
         rgb::RGB,
+        test_utils::{bulb_with_methods, ScriptedConnection},
     };
 
     use super::{
-        Cron, CronType, MethodCallError, MockTcpConnection, MusicMode, Scene, StringVecResponse,
-        TransitionMode, TEST_OK_VAL,
+        encode_name, Cron, CronType, MethodArg, MethodCallError, MusicMode, PowerMode, Request,
+        Scene, StringVecResponse, TransitionMode,
     };
 
-    fn one_rng() -> StepRng {
-        mock::StepRng::new(1, 0)
+    struct MockTcpConnection {
+        when_written: String,
+        return_val: String,
+        written_val: Option<String>,
+    }
+
+    impl Read for MockTcpConnection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self
+                .written_val
+                .clone()
+                .unwrap()
+                .trim()
+                .eq(self.when_written.trim())
+            {
+                let reply = format!("{}\r\n", self.return_val);
+                let bytes = reply.as_bytes();
+
+                for (i, elem) in buf.iter_mut().enumerate() {
+                    if i >= bytes.len() {
+                        break;
+                    }
+                    *elem = bytes[i];
+                }
+
+                return io::Result::Ok(usize::min(bytes.len(), buf.len()));
+            }
+            io::Result::Ok(0)
+        }
     }
 
+    impl Write for MockTcpConnection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written_val = Some(String::from_utf8_lossy(buf).to_string());
+            println!("mock written: {}", self.written_val.as_ref().unwrap());
+            println!("when written: {}", self.when_written);
+            io::Result::Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            io::Result::Ok(())
+        }
+    }
+
+    macro_rules! set {
+        ( $( $x:expr ),* ) => {  // Match zero or more comma delimited items
+            {
+                let mut temp_set = std::collections::HashSet::new();  // Create a mutable HashSet
+                $(
+                    temp_set.insert($x); // Insert each item matched into the HashSet
+                )*
+                temp_set // Return the populated HashSet
+            }
+        };
+    }
+
+    const TEST_OK_VAL: &str = "{\"id\":1, \"result\":[\"ok\"]}";
+
     fn make_bulb_with_method(method: Method) -> Bulb {
         Bulb {
             id: "".to_string(),
-            model: "".to_string(),
+            model: "color".to_string(),
             fw_ver: "".to_string(),
             support: set![method],
             power: crate::power::Power::Off,
@@ -740,20 +768,18 @@ mod tests {
     fn conn_with_method(
         method: Method,
         mock: MockTcpConnection,
-    ) -> BulbConnection<MockTcpConnection, StepRng> {
+    ) -> BulbConnection<MockTcpConnection> {
         let mock_bulb = make_bulb_with_method(method);
 
-        return BulbConnection {
-            bulb: mock_bulb,
-            connection: Mutex::new(mock),
-            rng: one_rng(),
-        };
+        BulbConnection::from_connection(mock_bulb, mock)
     }
 
     #[test]
     fn get_prop_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"get_prop\",\"params\":[\"power\", \"not_exist\", \"bright\"]}".to_string(),
+            when_written:
+                "{\"id\":1,\"method\":\"get_prop\",\"params\":[\"power\",\"not_exist\",\"bright\"]}"
+                    .to_string(),
             return_val: "{\"id\":1, \"result\":[\"on\", \"\", \"100\"]}".to_string(),
             written_val: None,
         };
@@ -769,10 +795,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn get_prop_name_test() {
+        let mock = MockTcpConnection {
+            when_written: "{\"id\":1,\"method\":\"get_prop\",\"params\":[\"name\",\"power\"]}"
+                .to_string(),
+            return_val: "{\"id\":1, \"result\":[\"5bGF6ZaT\", \"on\"]}".to_string(),
+            written_val: None,
+        };
+        let mut conn = conn_with_method(Method::GetProp, mock);
+
+        let result = conn.get_prop(&["name", "power"]).unwrap().result;
+        assert_eq!(result, vec!["居間", "on"]);
+    }
+
     #[test]
     fn set_ct_abx_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"set_ct_abx\",\"params\":[3500, \"smooth\", 500]}"
+            when_written: "{\"id\":1,\"method\":\"set_ct_abx\",\"params\":[3500,\"smooth\",500]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -784,10 +824,85 @@ mod tests {
         assert_ok_result(result);
     }
 
+    #[test]
+    fn set_ct_abx_model_range_test() {
+        let mock = MockTcpConnection {
+            when_written: "{\"id\":1,\"method\":\"set_ct_abx\",\"params\":[2700,\"sudden\",50]}"
+                .to_string(),
+            return_val: TEST_OK_VAL.to_string(),
+            written_val: None,
+        };
+        let mut conn = conn_with_method(Method::SetCtAbx, mock);
+        conn.bulb.model = "ceiling1".to_string();
+
+        assert!(matches!(
+            conn.set_ct_abx(2000, TransitionMode::Sudden)
+                .unwrap_err()
+                .kind(),
+            MethodCallError::BadRequest
+        ));
+        assert_ok_result(conn.set_ct_abx(2700, TransitionMode::Sudden));
+    }
+
+    #[test]
+    fn model_capabilities_test() {
+        let mock = MockTcpConnection {
+            when_written: "{\"id\":1,\"method\":\"set_power\",\"params\":[\"on\",\"sudden\",50,5]}"
+                .to_string(),
+            return_val: TEST_OK_VAL.to_string(),
+            written_val: None,
+        };
+        let mut conn = conn_with_method(Method::SetPower, mock);
+        for method in &[Method::SetRgb, Method::SetScene, Method::StartCf] {
+            conn.bulb.support.insert(*method);
+        }
+        let red = RGB { r: 255, g: 0, b: 0 };
+        let flow = ColorFlow::builder().ct(2000, 50, 500).build().unwrap();
+
+        // A mono ceiling light, 2700 ~ 6500 K.
+        conn.bulb.model = "ceiling1".to_string();
+        assert!(matches!(
+            conn.set_rgb(&red, TransitionMode::Sudden)
+                .unwrap_err()
+                .kind(),
+            MethodCallError::UnsupportedMethod
+        ));
+        assert!(matches!(
+            conn.set_scene(&Scene::Color(&red, 50)).unwrap_err().kind(),
+            MethodCallError::UnsupportedMethod
+        ));
+        assert!(matches!(
+            conn.set_scene(&Scene::Ct(2000, 50)).unwrap_err().kind(),
+            MethodCallError::BadRequest
+        ));
+        assert!(matches!(
+            conn.start_cf(&flow).unwrap_err().kind(),
+            MethodCallError::BadRequest
+        ));
+
+        conn.bulb.model = "color".to_string();
+        assert!(matches!(
+            conn.set_power(
+                Power::On,
+                TransitionMode::Sudden,
+                Some(PowerMode::NightLight)
+            )
+            .unwrap_err()
+            .kind(),
+            MethodCallError::UnsupportedMethod
+        ));
+        conn.bulb.model = "ceiling1".to_string();
+        assert_ok_result(conn.set_power(
+            Power::On,
+            TransitionMode::Sudden,
+            Some(PowerMode::NightLight),
+        ));
+    }
+
     #[test]
     fn set_rgb_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"set_rgb\",\"params\":[255, \"smooth\", 500]}"
+            when_written: "{\"id\":1,\"method\":\"set_rgb\",\"params\":[255,\"smooth\",500]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -805,7 +920,7 @@ mod tests {
     #[test]
     fn set_hsv_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"set_hsv\",\"params\":[255, 45, \"smooth\", 500]}"
+            when_written: "{\"id\":1,\"method\":\"set_hsv\",\"params\":[255,45,\"smooth\",500]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -826,7 +941,7 @@ mod tests {
     #[test]
     fn set_bright_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"set_bright\",\"params\":[50, \"smooth\", 500]}"
+            when_written: "{\"id\":1,\"method\":\"set_bright\",\"params\":[50,\"smooth\",500]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -841,9 +956,8 @@ mod tests {
     #[test]
     fn set_power_test() {
         let mock = MockTcpConnection {
-            when_written:
-                "{\"id\":1,\"method\":\"set_power\",\"params\":[\"on\", \"smooth\", 500]}"
-                    .to_string(),
+            when_written: "{\"id\":1,\"method\":\"set_power\",\"params\":[\"on\",\"smooth\",500]}"
+                .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
         };
@@ -875,7 +989,7 @@ mod tests {
     fn start_cf_test() {
         let mock = MockTcpConnection {
             when_written:
-                "{\"id\":1,\"method\":\"start_cf\",\"params\":[4, 2, \"1000,2,2700,100,500,1,255,10,5000,7,0,0,500,2,5000,1\"]}"
+                "{\"id\":1,\"method\":\"start_cf\",\"params\":[4,2,\"1000,2,2700,100,500,1,255,10,5000,7,0,0,500,2,5000,1\"]}"
                     .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -935,7 +1049,7 @@ mod tests {
     #[test]
     fn set_scene_color_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"set_scene\",\"params\":[\"color\", 65280, 70]}"
+            when_written: "{\"id\":1,\"method\":\"set_scene\",\"params\":[\"color\",65280,70]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -949,7 +1063,7 @@ mod tests {
     #[test]
     fn set_scene_hsv_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"set_scene\",\"params\":[\"hsv\", 300, 70, 100]}"
+            when_written: "{\"id\":1,\"method\":\"set_scene\",\"params\":[\"hsv\",300,70,100]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -969,7 +1083,7 @@ mod tests {
     #[test]
     fn set_scene_ct_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"set_scene\",\"params\":[\"ct\", 5400, 100]}"
+            when_written: "{\"id\":1,\"method\":\"set_scene\",\"params\":[\"ct\",5400,100]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -983,7 +1097,7 @@ mod tests {
     #[test]
     fn set_scene_cf_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"set_scene\",\"params\":[\"cf\", 0, 0, \"1000,2,2700,100,500,1,255,10,5000,7,0,0,500,2,5000,1\"]}"
+            when_written: "{\"id\":1,\"method\":\"set_scene\",\"params\":[\"cf\",0,0,\"1000,2,2700,100,500,1,255,10,5000,7,0,0,500,2,5000,1\"]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1034,7 +1148,7 @@ mod tests {
     fn set_scene_auto_delay_off_test() {
         let mock = MockTcpConnection {
             when_written:
-                "{\"id\":1,\"method\":\"set_scene\",\"params\":[\"auto_delay_off\", 50, 5]}"
+                "{\"id\":1,\"method\":\"set_scene\",\"params\":[\"auto_delay_off\",50,5]}"
                     .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1048,7 +1162,7 @@ mod tests {
     #[test]
     fn cron_add_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"cron_add\",\"params\":[0, 14]}".to_string(),
+            when_written: "{\"id\":1,\"method\":\"cron_add\",\"params\":[0,14]}".to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
         };
@@ -1099,7 +1213,7 @@ mod tests {
     #[test]
     fn set_adjust_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"set_adjust\",\"params\":[\"increase\", \"ct\"]}"
+            when_written: "{\"id\":1,\"method\":\"set_adjust\",\"params\":[\"increase\",\"ct\"]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1116,7 +1230,7 @@ mod tests {
     fn set_music_on_test() {
         let mock = MockTcpConnection {
             when_written:
-                "{\"id\":1,\"method\":\"set_music\",\"params\":[1, \"192.168.0.2\", 54321]}"
+                "{\"id\":1,\"method\":\"set_music\",\"params\":[1,\"192.168.0.2\",54321]}"
                     .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1153,11 +1267,28 @@ mod tests {
         assert_ok_result(conn.set_name("my_bulb"));
     }
 
+    #[test]
+    fn set_name_non_ascii_test() {
+        let mock = MockTcpConnection {
+            when_written: "{\"id\":1,\"method\":\"set_name\",\"params\":[\"5bGF6ZaT\"]}"
+                .to_string(),
+            return_val: TEST_OK_VAL.to_string(),
+            written_val: None,
+        };
+        let mut conn = conn_with_method(Method::SetName, mock);
+
+        assert_ok_result(conn.set_name("居間"));
+        assert!(matches!(
+            conn.set_name(&"間".repeat(17)).unwrap_err().kind(),
+            MethodCallError::BadRequest
+        ));
+    }
+
     #[test]
     fn bg_set_adjust_test() {
         let mock = MockTcpConnection {
             when_written:
-                "{\"id\":1,\"method\":\"bg_set_adjust\",\"params\":[\"increase\", \"ct\"]}"
+                "{\"id\":1,\"method\":\"bg_set_adjust\",\"params\":[\"increase\",\"ct\"]}"
                     .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1174,7 +1305,7 @@ mod tests {
     fn bg_set_ct_abx_test() {
         let mock = MockTcpConnection {
             when_written:
-                "{\"id\":1,\"method\":\"bg_set_ct_abx\",\"params\":[3500, \"smooth\", 500]}"
+                "{\"id\":1,\"method\":\"bg_set_ct_abx\",\"params\":[3500,\"smooth\",500]}"
                     .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1189,7 +1320,7 @@ mod tests {
     #[test]
     fn bg_set_rgb_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"bg_set_rgb\",\"params\":[255, \"smooth\", 500]}"
+            when_written: "{\"id\":1,\"method\":\"bg_set_rgb\",\"params\":[255,\"smooth\",500]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1207,9 +1338,8 @@ mod tests {
     #[test]
     fn bg_set_hsv_test() {
         let mock = MockTcpConnection {
-            when_written:
-                "{\"id\":1,\"method\":\"bg_set_hsv\",\"params\":[255, 45, \"smooth\", 500]}"
-                    .to_string(),
+            when_written: "{\"id\":1,\"method\":\"bg_set_hsv\",\"params\":[255,45,\"smooth\",500]}"
+                .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
         };
@@ -1229,9 +1359,8 @@ mod tests {
     #[test]
     fn bg_set_bright_test() {
         let mock = MockTcpConnection {
-            when_written:
-                "{\"id\":1,\"method\":\"bg_set_bright\",\"params\":[50, \"smooth\", 500]}"
-                    .to_string(),
+            when_written: "{\"id\":1,\"method\":\"bg_set_bright\",\"params\":[50,\"smooth\",500]}"
+                .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
         };
@@ -1246,7 +1375,7 @@ mod tests {
     fn bg_set_power_test() {
         let mock = MockTcpConnection {
             when_written:
-                "{\"id\":1,\"method\":\"bg_set_power\",\"params\":[\"on\", \"smooth\", 500]}"
+                "{\"id\":1,\"method\":\"bg_set_power\",\"params\":[\"on\",\"smooth\",500]}"
                     .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1279,7 +1408,7 @@ mod tests {
     fn bg_start_cf_test() {
         let mock = MockTcpConnection {
             when_written:
-                "{\"id\":1,\"method\":\"bg_start_cf\",\"params\":[4, 2, \"1000,2,2700,100,500,1,255,10,5000,7,0,0,500,2,5000,1\"]}"
+                "{\"id\":1,\"method\":\"bg_start_cf\",\"params\":[4,2,\"1000,2,2700,100,500,1,255,10,5000,7,0,0,500,2,5000,1\"]}"
                     .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1339,9 +1468,8 @@ mod tests {
     #[test]
     fn bg_set_scene_color_test() {
         let mock = MockTcpConnection {
-            when_written:
-                "{\"id\":1,\"method\":\"bg_set_scene\",\"params\":[\"color\", 65280, 70]}"
-                    .to_string(),
+            when_written: "{\"id\":1,\"method\":\"bg_set_scene\",\"params\":[\"color\",65280,70]}"
+                .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
         };
@@ -1354,9 +1482,8 @@ mod tests {
     #[test]
     fn bg_set_scene_hsv_test() {
         let mock = MockTcpConnection {
-            when_written:
-                "{\"id\":1,\"method\":\"bg_set_scene\",\"params\":[\"hsv\", 300, 70, 100]}"
-                    .to_string(),
+            when_written: "{\"id\":1,\"method\":\"bg_set_scene\",\"params\":[\"hsv\",300,70,100]}"
+                .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
         };
@@ -1375,7 +1502,7 @@ mod tests {
     #[test]
     fn bg_set_scene_ct_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"bg_set_scene\",\"params\":[\"ct\", 5400, 100]}"
+            when_written: "{\"id\":1,\"method\":\"bg_set_scene\",\"params\":[\"ct\",5400,100]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1389,7 +1516,7 @@ mod tests {
     #[test]
     fn bg_set_scene_cf_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"bg_set_scene\",\"params\":[\"cf\", 0, 0, \"1000,2,2700,100,500,1,255,10,5000,7,0,0,500,2,5000,1\"]}"
+            when_written: "{\"id\":1,\"method\":\"bg_set_scene\",\"params\":[\"cf\",0,0,\"1000,2,2700,100,500,1,255,10,5000,7,0,0,500,2,5000,1\"]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1440,7 +1567,7 @@ mod tests {
     fn bg_set_scene_auto_delay_off_test() {
         let mock = MockTcpConnection {
             when_written:
-                "{\"id\":1,\"method\":\"bg_set_scene\",\"params\":[\"auto_delay_off\", 50, 5]}"
+                "{\"id\":1,\"method\":\"bg_set_scene\",\"params\":[\"auto_delay_off\",50,5]}"
                     .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1493,8 +1620,7 @@ mod tests {
     #[test]
     fn adjust_bright_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"adjust_bright\",\"params\":[20, 500]}"
-                .to_string(),
+            when_written: "{\"id\":1,\"method\":\"adjust_bright\",\"params\":[20,500]}".to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
         };
@@ -1507,7 +1633,7 @@ mod tests {
     #[test]
     fn adjust_ct_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"adjust_ct\",\"params\":[20, 500]}".to_string(),
+            when_written: "{\"id\":1,\"method\":\"adjust_ct\",\"params\":[20,500]}".to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
         };
@@ -1520,7 +1646,7 @@ mod tests {
     #[test]
     fn adjust_color_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"adjust_color\",\"params\":[20, 500]}".to_string(),
+            when_written: "{\"id\":1,\"method\":\"adjust_color\",\"params\":[20,500]}".to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
         };
@@ -1533,7 +1659,7 @@ mod tests {
     #[test]
     fn bg_adjust_bright_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"bg_adjust_bright\",\"params\":[20, 500]}"
+            when_written: "{\"id\":1,\"method\":\"bg_adjust_bright\",\"params\":[20,500]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1547,7 +1673,7 @@ mod tests {
     #[test]
     fn bg_adjust_ct_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"bg_adjust_ct\",\"params\":[20, 500]}".to_string(),
+            when_written: "{\"id\":1,\"method\":\"bg_adjust_ct\",\"params\":[20,500]}".to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
         };
@@ -1560,7 +1686,7 @@ mod tests {
     #[test]
     fn bg_adjust_color_test() {
         let mock = MockTcpConnection {
-            when_written: "{\"id\":1,\"method\":\"bg_adjust_color\",\"params\":[20, 500]}"
+            when_written: "{\"id\":1,\"method\":\"bg_adjust_color\",\"params\":[20,500]}"
                 .to_string(),
             return_val: TEST_OK_VAL.to_string(),
             written_val: None,
@@ -1570,18 +1696,247 @@ mod tests {
 
         assert_ok_result(conn.bg_adjust_color(20, &Duration::from_millis(500)));
     }
-}
 
-fn create_message(id: i16, method: &Method, args: Vec<MethodArg>) -> String {
-    let arg_strs: Vec<String> = args.iter().map(|a| a.to_str()).collect();
-    let strs = [
-        "{\"id\":",
-        &id.to_string()[..],
-        ",\"method\":\"",
-        method.into(),
-        "\",\"params\":[",
-        &arg_strs.join(", "),
-        "]}\r\n",
-    ];
-    strs.join("")
+    #[test]
+    fn notification_before_reply_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"method\":\"props\",\"params\":{\"power\":\"on\",\"bright\":10}}\r\n",
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+        ]);
+        let mut conn = BulbConnection::from_connection(make_bulb_with_method(Method::Toggle), mock);
+        let notifications = conn.notifications();
+
+        assert_ok_result(conn.toggle());
+
+        let notification = notifications.try_recv().unwrap();
+        assert_eq!(notification.method, "props");
+        assert_eq!(notification.params.get("power").unwrap(), "on");
+        assert_eq!(notification.params.get("bright").unwrap(), "10");
+        assert!(notifications.try_recv().is_err());
+    }
+
+    #[test]
+    fn notification_coalesced_with_reply_test() {
+        let mock = ScriptedConnection::new(&[
+            concat!(
+                "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+                "{\"method\":\"props\",\"params\":{\"power\":\"off\"}}\r\n",
+            ),
+            "{\"id\":2, \"result\":[\"ok\"]}\r\n",
+        ]);
+        let mut conn = BulbConnection::from_connection(make_bulb_with_method(Method::Toggle), mock);
+        let notifications = conn.notifications();
+
+        assert_ok_result(conn.toggle());
+        // Frames after the reply stay buffered until the next call reads.
+        assert!(notifications.try_recv().is_err());
+        assert_ok_result(conn.toggle());
+
+        let notification = notifications.try_recv().unwrap();
+        assert_eq!(notification.params.get("power").unwrap(), "off");
+    }
+
+    #[test]
+    fn fragmented_reply_test() {
+        let mock = ScriptedConnection::new(&["{\"id\":1, \"res", "ult\":[\"ok\"]", "}\r\n"]);
+        let mut conn = BulbConnection::from_connection(make_bulb_with_method(Method::Toggle), mock);
+
+        assert_ok_result(conn.toggle());
+    }
+
+    #[test]
+    fn coalesced_replies_test() {
+        let mock = ScriptedConnection::new(&[concat!(
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":2, \"result\":[\"on\"]}\r\n",
+        )]);
+        let mut conn =
+            BulbConnection::from_connection(make_bulb_with_method(Method::GetProp), mock);
+
+        assert_ok_result(conn.get_prop(&["power"]));
+        assert_eq!(conn.get_prop(&["power"]).unwrap().result, vec!["on"]);
+    }
+
+    #[test]
+    fn long_reply_test() {
+        let flow_params = vec!["1000,1,16711680,100"; 200].join(",");
+        let reply = format!("{{\"id\":1, \"result\":[\"{}\"]}}\r\n", flow_params);
+        let mock = ScriptedConnection::new(&[&reply]);
+        let mut conn =
+            BulbConnection::from_connection(make_bulb_with_method(Method::GetProp), mock);
+
+        let result = conn.get_prop(&["flow_params"]).unwrap();
+        assert_eq!(result.result, vec![flow_params]);
+    }
+
+    #[test]
+    fn stale_reply_skipped_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"id\":7, \"result\":[\"off\"]}\r\n",
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+        ]);
+        let mut conn = BulbConnection::from_connection(make_bulb_with_method(Method::Toggle), mock);
+
+        assert_ok_result(conn.toggle());
+    }
+
+    #[test]
+    fn response_timeout_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut bulb = make_bulb_with_method(Method::Toggle);
+        bulb.ip_address = listener.local_addr().unwrap().to_string();
+
+        let (timed_out, wait_for_timeout) = mpsc::channel();
+        let bulb_side = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut requests = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            requests.read_line(&mut request).unwrap();
+            wait_for_timeout.recv().unwrap();
+            requests.read_line(&mut request).unwrap();
+            (&stream)
+                .write_all(
+                    b"{\"id\":1, \"result\":[\"ok\"]}\r\n{\"id\":2, \"result\":[\"ok\"]}\r\n",
+                )
+                .unwrap();
+        });
+
+        let options = ConnectionOptions::new()
+            .connect_timeout(Duration::from_secs(1))
+            .response_timeout(Duration::from_millis(50));
+        let mut conn = TcpConnection::with_options(bulb, &options).unwrap();
+
+        assert!(matches!(
+            conn.toggle().unwrap_err().kind(),
+            MethodCallError::Timeout
+        ));
+        timed_out.send(()).unwrap();
+        // The late reply to the first call is skipped.
+        let result = conn.toggle().unwrap();
+        assert_eq!(result.id, 2);
+
+        bulb_side.join().unwrap();
+    }
+
+    #[test]
+    fn error_context_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"id\":1, \"error\":{\"code\":-1, \"message\":\"client quota exceeded\"}}\r\n",
+        ]);
+        let mut bulb = make_bulb_with_method(Method::Toggle);
+        bulb.id = "0x000000000015243f".to_string();
+        let mut conn = BulbConnection::from_connection(bulb, mock);
+
+        let err = conn.toggle().unwrap_err();
+        assert_eq!(err.method(), Some(Method::Toggle));
+        assert_eq!(err.bulb_id(), Some("0x000000000015243f"));
+        assert_eq!(
+            err.to_string(),
+            "toggle on bulb 0x000000000015243f: bulb replied with error -1: client quota exceeded"
+        );
+        match err.kind() {
+            MethodCallError::ErrorResponse(response) => {
+                assert_eq!(response.id(), 1);
+                assert_eq!(response.error().code(), -1);
+                assert_eq!(response.error().kind(), BulbErrorKind::ClientQuotaExceeded);
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+
+        let err = conn.set_bright(101, TransitionMode::Sudden).unwrap_err();
+        assert!(matches!(err.kind(), MethodCallError::BadRequest));
+        assert_eq!(err.method(), Some(Method::SetBright));
+    }
+
+    #[test]
+    fn bulb_error_kind_test() {
+        let kind = |code: i32, message: &str| {
+            serde_json::from_value::<BulbErrorResponse>(serde_json::json!({
+                "code": code,
+                "message": message,
+            }))
+            .unwrap()
+            .kind()
+        };
+
+        assert_eq!(
+            kind(-1, "method not supported"),
+            BulbErrorKind::MethodNotSupported
+        );
+        assert_eq!(
+            kind(-1, "unsupported method"),
+            BulbErrorKind::MethodNotSupported
+        );
+        assert_eq!(kind(-1, "invalid params"), BulbErrorKind::InvalidParams);
+        assert_eq!(
+            kind(-1, "client quota exceeded"),
+            BulbErrorKind::ClientQuotaExceeded
+        );
+        assert_eq!(kind(-1, "invalid command"), BulbErrorKind::InvalidCommand);
+        assert_eq!(kind(-5000, "general error"), BulbErrorKind::GeneralError);
+        assert_eq!(kind(-2, "something new"), BulbErrorKind::Unknown);
+    }
+
+    #[test]
+    fn tricky_name_round_trip_test() {
+        let names = [
+            "Living \"Room\"",
+            "C:\\lamps\\desk",
+            "two\r\nlines",
+            "tab\there",
+            "K\u{fc}che \u{1f4a1}",
+            "\u{7}",
+        ];
+        for name in names.iter() {
+            let mock = ScriptedConnection::new(&["{\"id\":1, \"result\":[\"ok\"]}\r\n"]);
+            let mut conn =
+                BulbConnection::from_connection(make_bulb_with_method(Method::SetName), mock);
+            assert_ok_result(conn.set_name(name));
+
+            let written = conn.connection.lock().unwrap().written_str();
+            let line = written.strip_suffix("\r\n").unwrap();
+            assert!(!line.contains('\n') && !line.contains('\r'));
+
+            let request: Request = serde_json::from_str(line).unwrap();
+            assert_eq!(
+                request,
+                Request {
+                    id: 1,
+                    method: "set_name",
+                    params: vec![MethodArg::String(encode_name(name).unwrap())],
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn state_cache_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":2, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":3, \"error\":{\"code\":-1, \"message\":\"invalid params\"}}\r\n",
+            concat!(
+                "{\"method\":\"props\",\"params\":{\"power\":\"off\",\"ct\":6500,\"color_mode\":2}}\r\n",
+                "{\"id\":4, \"result\":[\"ok\"]}\r\n",
+            ),
+        ]);
+        let bulb = bulb_with_methods(&[Method::Toggle, Method::SetRgb, Method::SetBright]);
+        let mut conn = BulbConnection::from_connection(bulb, mock);
+
+        assert_ok_result(conn.toggle());
+        assert_eq!(conn.bulb.power, Power::On);
+
+        let blue = RGB { r: 0, g: 0, b: 255 };
+        assert_ok_result(conn.set_rgb(&blue, TransitionMode::Sudden));
+        assert_eq!(conn.bulb.color_mode, LightMode::Color(blue));
+
+        // A rejected call changes nothing.
+        assert!(conn.set_bright(40, TransitionMode::Sudden).is_err());
+        assert_eq!(conn.bulb.bright, 0);
+
+        assert_ok_result(conn.set_bright(10, TransitionMode::Sudden));
+        assert_eq!(conn.bulb.bright, 10);
+        assert_eq!(conn.bulb.power, Power::Off);
+        assert_eq!(conn.bulb.color_mode, LightMode::ColorTemperature(6500));
+    }
 }
@@ -0,0 +1,162 @@
+//! What each Yeelight model can do, keyed by the `model` the bulb reports
+//! in search responses.
+
+use std::ops::RangeInclusive;
+
+use crate::{
+    bulb::Bulb,
+    connection::{CT_MAX, CT_MIN},
+    method::Method,
+};
+
+/// What background lights take: any colour and the whole colour temperature
+/// range. Calls to them are checked against this the way calls to the main
+/// light are checked against its model's [`Capabilities`].
+pub const BACKGROUND: Capabilities = Capabilities {
+    color: true,
+    ..white(CT_MIN, CT_MAX)
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Lowest colour temperature of the main light, in Kelvin.
+    pub ct_min: u16,
+    /// Highest colour temperature of the main light, in Kelvin.
+    pub ct_max: u16,
+    /// The main light takes RGB and HSV colours.
+    pub color: bool,
+    /// There is a separately controlled background light.
+    pub background: bool,
+    /// The main light has a nightlight mode.
+    pub nightlight: bool,
+}
+
+const fn white(ct_min: u16, ct_max: u16) -> Capabilities {
+    Capabilities {
+        ct_min,
+        ct_max,
+        color: false,
+        background: false,
+        nightlight: false,
+    }
+}
+
+const fn color(ct_min: u16, ct_max: u16) -> Capabilities {
+    Capabilities {
+        color: true,
+        ..white(ct_min, ct_max)
+    }
+}
+
+const fn ceiling(background: bool) -> Capabilities {
+    Capabilities {
+        background,
+        nightlight: true,
+        ..white(2700, 6500)
+    }
+}
+
+/// Models by name, or by prefix when the name ends in `*`. The first match
+/// wins, so specific models come before the family they belong to.
+const MODELS: &[(&str, Capabilities)] = &[
+    ("mono*", white(2700, 2700)),
+    ("color2", color(2700, 6500)),
+    ("color*", color(1700, 6500)),
+    ("ct_bulb", white(2700, 6500)),
+    ("strip6", color(2700, 6500)),
+    ("strip*", color(1700, 6500)),
+    ("bslamp1", color(1700, 6500)),
+    (
+        "bslamp*",
+        Capabilities {
+            nightlight: true,
+            ..color(1700, 6500)
+        },
+    ),
+    ("ceiling4", ceiling(true)),
+    ("ceiling10", ceiling(true)),
+    ("ceiling20", ceiling(true)),
+    ("ceiling*", ceiling(false)),
+    ("ceila", ceiling(false)),
+    ("lamp1", white(2700, 5000)),
+    ("lamp4", white(2600, 5000)),
+    ("desklamp", white(2700, 6500)),
+];
+
+fn matches(pattern: &str, model: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => model.starts_with(prefix),
+        None => pattern == model,
+    }
+}
+
+impl Capabilities {
+    /// The capabilities of a model in the table, if it is there.
+    pub fn for_model(model: &str) -> Option<Capabilities> {
+        MODELS
+            .iter()
+            .find(|(pattern, _)| matches(pattern, model))
+            .map(|(_, capabilities)| *capabilities)
+    }
+
+    pub fn ct_range(&self) -> RangeInclusive<u16> {
+        self.ct_min..=self.ct_max
+    }
+}
+
+impl Bulb {
+    /// The capabilities of the bulb's model, which calls to the main light
+    /// are checked against. Models the table doesn't know
+    /// get the colour temperature range the protocol documents, colour and
+    /// a background light only if the bulb lists their methods, and no
+    /// nightlight.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::for_model(&self.model).unwrap_or(Capabilities {
+            color: self.support.contains(&Method::SetRgb) || self.support.contains(&Method::SetHsv),
+            background: self.support.contains(&Method::BgSetPower),
+            ..white(CT_MIN, CT_MAX)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Capabilities;
+    use crate::{connection::CT_MAX, method::Method, test_utils::bulb_with_methods};
+
+    #[test]
+    fn for_model_test() {
+        let color = Capabilities::for_model("color").unwrap();
+        assert!(color.color && !color.background);
+        assert_eq!(color.ct_range(), 1700..=6500);
+
+        assert_eq!(
+            Capabilities::for_model("color2").unwrap().ct_range(),
+            2700..=6500
+        );
+        assert!(!Capabilities::for_model("mono1").unwrap().color);
+        assert_eq!(Capabilities::for_model("stripe").unwrap().ct_min, 1700);
+
+        let ceiling = Capabilities::for_model("ceiling3").unwrap();
+        assert!(ceiling.nightlight && !ceiling.background && !ceiling.color);
+        assert!(Capabilities::for_model("ceiling4").unwrap().background);
+        assert!(Capabilities::for_model("bslamp2").unwrap().nightlight);
+        assert!(!Capabilities::for_model("bslamp1").unwrap().nightlight);
+
+        assert_eq!(Capabilities::for_model("lamp9"), None);
+        assert_eq!(Capabilities::for_model(""), None);
+    }
+
+    #[test]
+    fn unknown_model_test() {
+        let mut bulb = bulb_with_methods(&[Method::SetRgb, Method::SetCtAbx]);
+        bulb.model = "future_lamp".to_string();
+        let capabilities = bulb.capabilities();
+        assert!(capabilities.color);
+        assert!(!capabilities.background && !capabilities.nightlight);
+        assert_eq!(capabilities.ct_max, CT_MAX);
+
+        bulb.model = "ceiling1".to_string();
+        assert!(!bulb.capabilities().color);
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bulb::Bulb,
+    connection::{BulbConnection, MethodCallError, MusicMode, RequestIds},
+    light_methods::light_methods,
+    method::Method,
+    method_calls::{apply_call, create_message, io_error, MethodArg},
+};
+
+const DEFAULT_ACCEPT_TIMEOUT: Duration = Duration::from_secs(5);
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Accepts the connection a bulb opens back to us once music mode is on.
+///
+/// In music mode the bulb takes commands over that connection without
+/// replying to them and without counting them against its quota, which
+/// suits streaming many changes a second.
+pub struct MusicServer {
+    listener: TcpListener,
+    accept_timeout: Duration,
+}
+
+impl MusicServer {
+    /// Listens on `addr`. The bulb has to reach it, so this should be the
+    /// address of the interface on the bulb's network rather than a wildcard.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(MusicServer {
+            listener: TcpListener::bind(addr)?,
+            accept_timeout: DEFAULT_ACCEPT_TIMEOUT,
+        })
+    }
+
+    /// Listens on a free port of the local address that routes to `bulb`.
+    pub fn for_bulb(bulb: &Bulb) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(&bulb.ip_address)?;
+        MusicServer::bind((socket.local_addr()?.ip(), 0))
+    }
+
+    /// How long [`start`](Self::start) waits for the bulb to connect.
+    /// Five seconds by default.
+    pub fn accept_timeout(mut self, timeout: Duration) -> Self {
+        self.accept_timeout = timeout;
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Turns music mode on through `control` and waits for the bulb to
+    /// connect. Music mode is turned off again if it doesn't.
+    pub fn start<C: Read + Write>(
+        self,
+        control: &mut BulbConnection<C>,
+    ) -> Result<MusicConnection<'_, C>, MethodCallError> {
+        let addr = self.local_addr().map_err(|err| {
+            MethodCallError::IOError(err).in_call(Method::SetMusic, &control.bulb)
+        })?;
+        let host = addr.ip().to_string();
+        control.set_music(MusicMode::On(&host, addr.port() as usize))?;
+
+        match self.accept() {
+            Ok(stream) => Ok(MusicConnection {
+                control,
+                stream,
+                ids: RequestIds::new(),
+                _listener: self.listener,
+            }),
+            Err(err) => {
+                let _ = control.set_music(MusicMode::Off);
+                Err(err.in_call(Method::SetMusic, &control.bulb))
+            }
+        }
+    }
+
+    fn accept(&self) -> Result<TcpStream, MethodCallError> {
+        self.listener
+            .set_nonblocking(true)
+            .map_err(MethodCallError::IOError)?;
+        let deadline = Instant::now() + self.accept_timeout;
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream
+                        .set_nonblocking(false)
+                        .map_err(MethodCallError::IOError)?;
+                    return Ok(stream);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Err(MethodCallError::Timeout);
+                    }
+                    thread::sleep(ACCEPT_POLL_INTERVAL);
+                }
+                Err(err) => return Err(MethodCallError::IOError(err)),
+            }
+        }
+    }
+}
+
+/// Commands streamed to a bulb in music mode. Each call returns once the
+/// request is written, as the bulb doesn't reply. Dropping the connection
+/// turns music mode off through the control connection it was started on.
+pub struct MusicConnection<'a, C: Read + Write> {
+    control: &'a mut BulbConnection<C>,
+    stream: TcpStream,
+    ids: RequestIds,
+    _listener: TcpListener,
+}
+
+impl<C: Read + Write> Drop for MusicConnection<'_, C> {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+        let _ = self.control.set_music(MusicMode::Off);
+    }
+}
+
+impl<C: Read + Write> MusicConnection<'_, C> {
+    pub fn bulb(&self) -> &Bulb {
+        &self.control.bulb
+    }
+
+    fn send(
+        &mut self,
+        method: Method,
+        args: Result<Vec<MethodArg>, MethodCallError>,
+    ) -> Result<(), MethodCallError> {
+        self.checked_send(method, args)
+            .map_err(|err| err.in_call(method, &self.control.bulb))
+    }
+
+    fn checked_send(
+        &mut self,
+        method: Method,
+        args: Result<Vec<MethodArg>, MethodCallError>,
+    ) -> Result<(), MethodCallError> {
+        let args = args?;
+        if !self.control.bulb.support.contains(&method) {
+            return Err(MethodCallError::UnsupportedMethod);
+        }
+
+        let message = create_message(self.ids.next_id(), &method, args.clone());
+        self.stream
+            .write_all(message.as_bytes())
+            .map_err(io_error)?;
+        // The bulb doesn't reply in music mode, so assume the command took.
+        apply_call(&mut self.control.bulb, method, &args);
+        Ok(())
+    }
+
+    light_methods!(send -> Result<(), MethodCallError>, control.bulb);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    use super::MusicServer;
+    use crate::{
+        connection::{MethodCallError, TcpConnection, TransitionMode},
+        method::Method,
+        rgb::RGB,
+        test_utils::bulb_with_methods,
+    };
+
+    /// Plays the bulb: connects back to the address `set_music` asks for and
+    /// passes on every line it gets over the music connection.
+    fn bulb_stand_in(
+        control: TcpListener,
+        connect_back: bool,
+    ) -> (mpsc::Receiver<String>, thread::JoinHandle<Vec<String>>) {
+        let (music_lines, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let (stream, _) = control.accept().unwrap();
+            let mut requests = BufReader::new(stream.try_clone().unwrap());
+            let mut control_lines = Vec::new();
+            let mut music = None;
+
+            let mut line = String::new();
+            while requests.read_line(&mut line).unwrap() > 0 {
+                let request: serde_json::Value = serde_json::from_str(&line).unwrap();
+                let params = request["params"].as_array().unwrap();
+                if params[0] == 1 && connect_back {
+                    let addr = format!("{}:{}", params[1].as_str().unwrap(), params[2]);
+                    let stream = TcpStream::connect(addr).unwrap();
+                    let sender = music_lines.clone();
+                    music = Some(thread::spawn(move || {
+                        for line in BufReader::new(stream).lines() {
+                            sender.send(line.unwrap()).unwrap();
+                        }
+                    }));
+                }
+                let reply = format!("{{\"id\":{}, \"result\":[\"ok\"]}}\r\n", request["id"]);
+                (&stream).write_all(reply.as_bytes()).unwrap();
+                control_lines.push(line.trim().to_string());
+                line.clear();
+                if params[0] == 0 {
+                    break;
+                }
+            }
+            if let Some(music) = music {
+                music.join().unwrap();
+            }
+            control_lines
+        });
+        (receiver, handle)
+    }
+
+    fn control_connection(listener: &TcpListener) -> TcpConnection {
+        let mut bulb = bulb_with_methods(&[Method::SetMusic, Method::SetRgb, Method::Toggle]);
+        bulb.ip_address = listener.local_addr().unwrap().to_string();
+        TcpConnection::new(bulb).unwrap()
+    }
+
+    #[test]
+    fn music_stream_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut control = control_connection(&listener);
+        let (music_lines, bulb) = bulb_stand_in(listener, true);
+
+        let server = MusicServer::bind("127.0.0.1:0").unwrap();
+        let mut music = server.start(&mut control).unwrap();
+        music
+            .set_rgb(&RGB { r: 0, g: 0, b: 255 }, TransitionMode::Sudden)
+            .unwrap();
+        music.toggle().unwrap();
+        assert!(matches!(
+            music
+                .set_bright(50, TransitionMode::Sudden)
+                .unwrap_err()
+                .kind(),
+            MethodCallError::UnsupportedMethod
+        ));
+
+        assert_eq!(
+            music_lines.recv().unwrap(),
+            "{\"id\":1,\"method\":\"set_rgb\",\"params\":[255,\"sudden\",50]}"
+        );
+        assert_eq!(
+            music_lines.recv().unwrap(),
+            "{\"id\":2,\"method\":\"toggle\",\"params\":[]}"
+        );
+
+        drop(music);
+        let control_lines = bulb.join().unwrap();
+        assert_eq!(control_lines.len(), 2);
+        assert!(control_lines[0].contains("\"params\":[1,\"127.0.0.1\""));
+        assert!(control_lines[1].contains("\"params\":[0]"));
+    }
+
+    #[test]
+    fn bulb_never_connects_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut control = control_connection(&listener);
+        let (_, bulb) = bulb_stand_in(listener, false);
+
+        let server = MusicServer::bind("127.0.0.1:0")
+            .unwrap()
+            .accept_timeout(Duration::from_millis(50));
+        assert!(matches!(
+            server.start(&mut control).err().unwrap().kind(),
+            MethodCallError::Timeout
+        ));
+
+        let control_lines = bulb.join().unwrap();
+        assert!(control_lines[1].contains("\"params\":[0]"));
+    }
+}
@@ -0,0 +1,68 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Longest name a bulb stores, in bytes.
+pub const MAX_NAME_BYTES: usize = 64;
+
+/// Turns a name into what gets stored on the bulb. ASCII names are stored
+/// as they are, anything else base64 encoded as the Yeelight docs suggest.
+/// Returns `None` when the stored name wouldn't fit in [`MAX_NAME_BYTES`].
+pub fn encode_name(name: &str) -> Option<String> {
+    let stored = if name.is_ascii() {
+        name.to_string()
+    } else {
+        STANDARD.encode(name)
+    };
+
+    if stored.len() > MAX_NAME_BYTES {
+        None
+    } else {
+        Some(stored)
+    }
+}
+
+/// Turns a name read from the bulb back into what was set. Names that
+/// don't decode to non-ASCII text were stored as they are and are returned
+/// unchanged, so plain names that happen to be valid base64 stay intact.
+pub fn decode_name(stored: &str) -> String {
+    STANDARD
+        .decode(stored)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .filter(|name| !name.is_ascii() && !name.chars().any(char::is_control))
+        .unwrap_or_else(|| stored.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_name, encode_name, MAX_NAME_BYTES};
+
+    #[test]
+    fn ascii_name_test() {
+        assert_eq!(encode_name("Kitchen").unwrap(), "Kitchen");
+        assert_eq!(decode_name("Kitchen"), "Kitchen");
+        // Valid base64, but not of a non-ASCII name.
+        assert_eq!(decode_name("Lamp"), "Lamp");
+        assert_eq!(decode_name("ZGVzaw=="), "ZGVzaw==");
+    }
+
+    #[test]
+    fn non_ascii_name_test() {
+        for name in ["Oturma Odası", "居間", "Küche 💡"].iter() {
+            let stored = encode_name(name).unwrap();
+            assert!(stored.is_ascii());
+            assert_eq!(decode_name(&stored), *name);
+        }
+        assert_eq!(encode_name("居間").unwrap(), "5bGF6ZaT");
+    }
+
+    #[test]
+    fn name_too_long_test() {
+        let ascii = "a".repeat(MAX_NAME_BYTES);
+        assert_eq!(encode_name(&ascii).unwrap(), ascii);
+        assert!(encode_name(&format!("{}a", ascii)).is_none());
+
+        // 16 characters of 3 bytes each encode to exactly 64 bytes.
+        assert!(encode_name(&"間".repeat(16)).is_some());
+        assert!(encode_name(&"間".repeat(17)).is_none());
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    time::Instant,
+};
+
+use crate::{
+    connection::{BulbConnection, MethodCallError, MusicMode, StringVecResponse},
+    light_methods::{control_methods, light_methods},
+    method::Method,
+    method_calls::{
+        apply_call, create_message, decode_names, get_prop_args, io_error, music_args, next_reply,
+        parse_response, read_frames, reply_id, MethodArg,
+    },
+};
+
+/// Requests that are written to the bulb back-to-back before any reply is
+/// read. Replies are matched to their requests by id, in whatever order the
+/// bulb sends them.
+///
+/// Validation happens while queueing, so a bad request is reported by the
+/// queueing method and never sent.
+pub struct Pipeline<'a, C: Read + Write> {
+    connection: &'a mut BulbConnection<C>,
+    requests: Vec<(Method, Vec<MethodArg>)>,
+    /// Index of the last queued `set_music` and whether it turns music mode
+    /// on, applied to the connection once its reply is in.
+    music_mode: Option<(usize, bool)>,
+}
+
+impl<C: Read + Write> BulbConnection<C> {
+    pub fn pipeline(&mut self) -> Pipeline<'_, C> {
+        Pipeline {
+            connection: self,
+            requests: Vec::new(),
+            music_mode: None,
+        }
+    }
+}
+
+impl<C: Read + Write> Pipeline<'_, C> {
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Writes every queued request, then reads until each one has its reply.
+    /// The outer error means the exchange itself broke down and isn't tied to
+    /// a single method; otherwise the results are in the order the requests
+    /// were queued.
+    ///
+    /// On a reconnecting connection a broken stream is replaced, but the
+    /// requests are not sent again since some may already have been applied.
+    pub fn send(self) -> Result<Vec<Result<StringVecResponse, MethodCallError>>, MethodCallError> {
+        let connection = self.connection;
+        connection.admit_batch(self.requests.len())?;
+        if connection.is_broken() {
+            connection.reconnect()?;
+        }
+
+        let result = exchange(connection, self.requests);
+        match &result {
+            Ok(results) => {
+                if let Some((index, on)) = self.music_mode {
+                    if results[index].is_ok() {
+                        connection.music_mode = on;
+                    }
+                }
+            }
+            Err(MethodCallError::IOError(_)) if connection.can_reconnect() => {
+                let _ = connection.reconnect();
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    fn queue(
+        &mut self,
+        method: Method,
+        args: Result<Vec<MethodArg>, MethodCallError>,
+    ) -> Result<&mut Self, MethodCallError> {
+        let args = args.map_err(|err| err.in_call(method, &self.connection.bulb))?;
+        if !self.connection.bulb.support.contains(&method) {
+            return Err(MethodCallError::UnsupportedMethod.in_call(method, &self.connection.bulb));
+        }
+        // Ids only repeat after i16::MAX requests, keep every queued one unique.
+        if self.requests.len() >= i16::MAX as usize {
+            return Err(MethodCallError::BadRequest.in_call(method, &self.connection.bulb));
+        }
+
+        self.requests.push((method, args));
+        Ok(self)
+    }
+
+    pub fn get_prop(&mut self, props: &[&str]) -> Result<&mut Self, MethodCallError> {
+        self.queue(Method::GetProp, get_prop_args(props))
+    }
+
+    pub fn set_music(&mut self, mode: MusicMode) -> Result<&mut Self, MethodCallError> {
+        let on = matches!(mode, MusicMode::On(..));
+        self.queue(Method::SetMusic, Ok(music_args(mode)))?;
+        self.music_mode = Some((self.requests.len() - 1, on));
+        Ok(self)
+    }
+
+    light_methods!(queue -> Result<&mut Self, MethodCallError>, connection.bulb);
+    control_methods!(queue -> Result<&mut Self, MethodCallError>, connection.bulb);
+}
+
+fn exchange<C: Read + Write>(
+    connection: &mut BulbConnection<C>,
+    requests: Vec<(Method, Vec<MethodArg>)>,
+) -> Result<Vec<Result<StringVecResponse, MethodCallError>>, MethodCallError> {
+    let mut conn = connection
+        .connection
+        .lock()
+        .map_err(|_| MethodCallError::SynchronizationError)?;
+
+    let mut pending: HashMap<i16, usize> = HashMap::with_capacity(requests.len());
+    let mut calls = Vec::with_capacity(requests.len());
+    for (index, (method, args)) in requests.into_iter().enumerate() {
+        let id = connection.ids.next_id();
+        let message = create_message(id, &method, args.clone());
+        conn.write_all(message.as_bytes()).map_err(io_error)?;
+        pending.insert(id, index);
+        calls.push((method, args));
+    }
+
+    let mut results: Vec<Option<Result<StringVecResponse, MethodCallError>>> =
+        (0..pending.len()).map(|_| None).collect();
+
+    let deadline = connection
+        .response_timeout
+        .map(|timeout| Instant::now() + timeout);
+    while !pending.is_empty() {
+        let (bulb, subscribers) = (&mut connection.bulb, &mut connection.subscribers);
+        if let Some(reply) = next_reply(&mut connection.frames, |n| subscribers.deliver(bulb, n))? {
+            let index = reply_id(&reply).and_then(|id| pending.remove(&id).map(|i| (id, i)));
+            if let Some((id, index)) = index {
+                let (method, args) = &calls[index];
+                let mut result = parse_response(&reply, id);
+                if let Ok(response) = &mut result {
+                    apply_call(&mut connection.bulb, *method, args);
+                    if *method == Method::GetProp {
+                        let props = args.iter().filter_map(|arg| match arg {
+                            MethodArg::String(prop) => Some(prop.as_str()),
+                            MethodArg::Int(_) => None,
+                        });
+                        decode_names(props, response);
+                    }
+                }
+                results[index] = Some(result.map_err(|err| err.in_call(*method, &connection.bulb)));
+            }
+            continue;
+        }
+
+        read_frames(
+            &mut *conn,
+            &mut connection.frames,
+            deadline,
+            connection.read_timeout_setter,
+        )?;
+    }
+
+    Ok(results.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        connection::{BulbConnection, MethodCallError, MusicMode, TransitionMode},
+        method::Method,
+        rgb::RGB,
+        test_utils::{bulb_with_methods, ScriptedConnection},
+    };
+
+    #[test]
+    fn out_of_order_replies_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"id\":2, \"result\":[\"ok\"]}\r\n",
+            concat!(
+                "{\"method\":\"props\",\"params\":{\"bright\":\"50\"}}\r\n",
+                "{\"id\":3, \"error\":{\"code\":-1, \"message\":\"unsupported method\"}}\r\n",
+                "{\"id\":1, \"result\":[\"on\"]}\r\n",
+            ),
+        ]);
+        let bulb = bulb_with_methods(&[Method::GetProp, Method::SetBright, Method::Toggle]);
+        let mut conn = BulbConnection::from_connection(bulb, mock);
+        let notifications = conn.notifications();
+
+        let mut pipeline = conn.pipeline();
+        pipeline
+            .get_prop(&["power"])
+            .unwrap()
+            .set_bright(50, TransitionMode::Sudden)
+            .unwrap()
+            .toggle()
+            .unwrap();
+        assert_eq!(pipeline.len(), 3);
+
+        let results = pipeline.send().unwrap();
+
+        assert_eq!(results[0].as_ref().unwrap().result, vec!["on"]);
+        assert_eq!(results[1].as_ref().unwrap().result, vec!["ok"]);
+        assert!(matches!(
+            results[2].as_ref().unwrap_err().kind(),
+            MethodCallError::ErrorResponse(_)
+        ));
+        assert!(notifications.try_recv().is_ok());
+
+        let written = conn.connection.lock().unwrap().written_str();
+        assert_eq!(
+            written,
+            concat!(
+                "{\"id\":1,\"method\":\"get_prop\",\"params\":[\"power\"]}\r\n",
+                "{\"id\":2,\"method\":\"set_bright\",\"params\":[50,\"sudden\",50]}\r\n",
+                "{\"id\":3,\"method\":\"toggle\",\"params\":[]}\r\n",
+            )
+        );
+    }
+
+    #[test]
+    fn queue_validation_test() {
+        let mock = ScriptedConnection::new(&[]);
+        let mut conn = BulbConnection::from_connection(bulb_with_methods(&[Method::SetRgb]), mock);
+
+        let mut pipeline = conn.pipeline();
+        assert!(matches!(
+            pipeline.toggle().err().unwrap().kind(),
+            MethodCallError::UnsupportedMethod
+        ));
+        assert!(matches!(
+            pipeline
+                .set_rgb(
+                    &RGB { r: 0, g: 0, b: 0 },
+                    TransitionMode::Smooth(Duration::from_millis(1))
+                )
+                .err()
+                .unwrap()
+                .kind(),
+            MethodCallError::BadRequest
+        ));
+        assert!(pipeline.is_empty());
+        assert!(pipeline.send().unwrap().is_empty());
+    }
+
+    #[test]
+    fn set_music_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n{\"id\":2, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":3, \"error\":{\"code\":-1, \"message\":\"general error\"}}\r\n",
+        ]);
+        let bulb = bulb_with_methods(&[Method::SetMusic, Method::Toggle]);
+        let mut conn = BulbConnection::from_connection(bulb, mock);
+
+        let mut pipeline = conn.pipeline();
+        pipeline
+            .set_music(MusicMode::On("127.0.0.1", 54321))
+            .unwrap()
+            .toggle()
+            .unwrap();
+        assert!(pipeline.send().unwrap().iter().all(Result::is_ok));
+        assert!(conn.music_mode());
+
+        let mut pipeline = conn.pipeline();
+        pipeline.set_music(MusicMode::Off).unwrap();
+        assert!(pipeline.send().unwrap()[0].is_err());
+        assert!(conn.music_mode());
+    }
+}
@@ -0,0 +1,365 @@
+use enum_iterator::IntoEnumIterator;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    io::{Read, Write},
+};
+
+use crate::{
+    connection::{BulbConnection, MethodCallError, StringVecResponse},
+    lightmode::LightMode,
+    method::Method,
+    power::Power,
+};
+
+/// A property that can be read with `get_prop`.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, IntoEnumIterator)]
+pub enum Property {
+    Power,
+    Bright,
+    Ct,
+    Rgb,
+    Hue,
+    Sat,
+    ColorMode,
+    Flowing,
+    DelayOff,
+    FlowParams,
+    MusicOn,
+    Name,
+    BgPower,
+    BgFlowing,
+    BgFlowParams,
+    BgCt,
+    BgLmode,
+    BgBright,
+    BgRgb,
+    BgHue,
+    BgSat,
+    NlBr,
+    ActiveMode,
+}
+
+impl TryFrom<&str> for Property {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let err: &'static str = "Doesn't match known properties.";
+        Property::into_enum_iter()
+            .find(|p| {
+                let prop_str: &str = p.into();
+                prop_str == value
+            })
+            .ok_or(err)
+    }
+}
+
+impl From<&Property> for &str {
+    fn from(val: &Property) -> Self {
+        match val {
+            Property::Power => "power",
+            Property::Bright => "bright",
+            Property::Ct => "ct",
+            Property::Rgb => "rgb",
+            Property::Hue => "hue",
+            Property::Sat => "sat",
+            Property::ColorMode => "color_mode",
+            Property::Flowing => "flowing",
+            Property::DelayOff => "delayoff",
+            Property::FlowParams => "flow_params",
+            Property::MusicOn => "music_on",
+            Property::Name => "name",
+            Property::BgPower => "bg_power",
+            Property::BgFlowing => "bg_flowing",
+            Property::BgFlowParams => "bg_flow_params",
+            Property::BgCt => "bg_ct",
+            Property::BgLmode => "bg_lmode",
+            Property::BgBright => "bg_bright",
+            Property::BgRgb => "bg_rgb",
+            Property::BgHue => "bg_hue",
+            Property::BgSat => "bg_sat",
+            Property::NlBr => "nl_br",
+            Property::ActiveMode => "active_mode",
+        }
+    }
+}
+
+/// Which of its two lights a ceiling lamp has on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ActiveMode {
+    Daylight,
+    Moonlight,
+}
+
+/// Everything `get_prop` reports about a bulb. Properties a bulb doesn't
+/// have come back empty and end up as `None`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BulbState {
+    pub power: Power,
+
+    // Percentage of maximum brightness, between 1 and 100.
+    pub bright: u8,
+
+    // None for bulbs without colour or colour temperature control.
+    pub color_mode: Option<LightMode>,
+
+    // Whether a color flow is running.
+    pub flowing: bool,
+
+    // Minutes left until the bulb turns itself off, 0 when no timer is set.
+    pub delay_off: u16,
+
+    // Expression of the running color flow, as passed to start_cf.
+    pub flow_params: Option<String>,
+
+    pub music_on: bool,
+
+    pub name: String,
+
+    // State of the background light of lamps that have one.
+    pub background: Option<BackgroundState>,
+
+    // Brightness of the night light, on lamps with one.
+    pub nl_br: Option<u8>,
+
+    pub active_mode: Option<ActiveMode>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct BackgroundState {
+    pub power: Power,
+    pub bright: u8,
+    pub color_mode: LightMode,
+    pub flowing: bool,
+    pub flow_params: Option<String>,
+}
+
+fn value(values: &HashMap<Property, String>, prop: Property) -> Option<&String> {
+    values.get(&prop).filter(|v| !v.is_empty())
+}
+
+fn flag(values: &HashMap<Property, String>, prop: Property) -> Option<bool> {
+    match value(values, prop)?.as_str() {
+        "1" | "on" => Some(true),
+        "0" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Reads a light mode out of the given colour properties, the way discovery
+/// reports them.
+fn light_mode(
+    values: &HashMap<Property, String>,
+    props: [(Property, &str); 5],
+) -> Option<LightMode> {
+    let map: HashMap<String, String> = props
+        .iter()
+        .filter_map(|(prop, key)| value(values, *prop).map(|v| (key.to_string(), v.clone())))
+        .collect();
+    LightMode::parse(&map)
+}
+
+impl BackgroundState {
+    pub fn parse(values: &HashMap<Property, String>) -> Option<BackgroundState> {
+        Some(BackgroundState {
+            power: value(values, Property::BgPower).and_then(|p| Power::try_from(p).ok())?,
+            bright: value(values, Property::BgBright)?.parse().ok()?,
+            color_mode: light_mode(
+                values,
+                [
+                    (Property::BgLmode, "color_mode"),
+                    (Property::BgRgb, "rgb"),
+                    (Property::BgCt, "ct"),
+                    (Property::BgHue, "hue"),
+                    (Property::BgSat, "sat"),
+                ],
+            )?,
+            flowing: flag(values, Property::BgFlowing).unwrap_or(false),
+            flow_params: value(values, Property::BgFlowParams).cloned(),
+        })
+    }
+}
+
+impl BulbState {
+    pub fn parse(values: &HashMap<Property, String>) -> Option<BulbState> {
+        Some(BulbState {
+            power: value(values, Property::Power).and_then(|p| Power::try_from(p).ok())?,
+            bright: value(values, Property::Bright)?.parse().ok()?,
+            color_mode: light_mode(
+                values,
+                [
+                    (Property::ColorMode, "color_mode"),
+                    (Property::Rgb, "rgb"),
+                    (Property::Ct, "ct"),
+                    (Property::Hue, "hue"),
+                    (Property::Sat, "sat"),
+                ],
+            ),
+            flowing: flag(values, Property::Flowing).unwrap_or(false),
+            delay_off: value(values, Property::DelayOff)
+                .and_then(|d| d.parse().ok())
+                .unwrap_or(0),
+            flow_params: value(values, Property::FlowParams).cloned(),
+            music_on: flag(values, Property::MusicOn).unwrap_or(false),
+            name: value(values, Property::Name).cloned().unwrap_or_default(),
+            background: BackgroundState::parse(values),
+            nl_br: value(values, Property::NlBr).and_then(|b| b.parse().ok()),
+            active_mode: match value(values, Property::ActiveMode).map(String::as_str) {
+                Some("0") => Some(ActiveMode::Daylight),
+                Some("1") => Some(ActiveMode::Moonlight),
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Pairs each requested property with its value in the `get_prop` reply.
+pub(crate) fn zip_props(
+    props: &[Property],
+    response: StringVecResponse,
+) -> Result<HashMap<Property, String>, MethodCallError> {
+    if response.result.len() != props.len() {
+        return Err(MethodCallError::ParseError);
+    }
+    Ok(props.iter().copied().zip(response.result).collect())
+}
+
+pub(crate) fn prop_names(props: &[Property]) -> Vec<&'static str> {
+    props.iter().map(|p| p.into()).collect()
+}
+
+impl<C: Read + Write> BulbConnection<C> {
+    /// Typed counterpart of [`get_prop`](Self::get_prop). Every requested
+    /// property is in the map, with an empty value if the bulb doesn't
+    /// know it. The name comes back decoded, see
+    /// [`decode_name`](crate::name::decode_name).
+    pub fn get_props(
+        &mut self,
+        props: &[Property],
+    ) -> Result<HashMap<Property, String>, MethodCallError> {
+        let response = self.get_prop(&prop_names(props))?;
+        zip_props(props, response).map_err(|err| err.in_call(Method::GetProp, &self.bulb))
+    }
+
+    /// Reads every property in one call.
+    pub fn get_state(&mut self) -> Result<BulbState, MethodCallError> {
+        let props: Vec<Property> = Property::into_enum_iter().collect();
+        let values = self.get_props(&props)?;
+        BulbState::parse(&values)
+            .ok_or_else(|| MethodCallError::ParseError.in_call(Method::GetProp, &self.bulb))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, convert::TryFrom};
+
+    use enum_iterator::IntoEnumIterator;
+
+    use super::{ActiveMode, BackgroundState, BulbState, Property};
+    use crate::{
+        connection::BulbConnection,
+        lightmode::{LightMode, HSV},
+        method::Method,
+        power::Power,
+        rgb::RGB,
+        test_utils::{bulb_with_methods, ScriptedConnection},
+    };
+
+    fn values(pairs: &[(Property, &str)]) -> HashMap<Property, String> {
+        pairs.iter().map(|(p, v)| (*p, v.to_string())).collect()
+    }
+
+    #[test]
+    fn property_names_test() {
+        for prop in Property::into_enum_iter() {
+            let name: &str = (&prop).into();
+            assert_eq!(Property::try_from(name), Ok(prop));
+        }
+        assert!(Property::try_from("bogus").is_err());
+    }
+
+    #[test]
+    fn ceiling_state_test() {
+        let state = BulbState::parse(&values(&[
+            (Property::Power, "on"),
+            (Property::Bright, "80"),
+            (Property::ColorMode, "2"),
+            (Property::Ct, "4000"),
+            (Property::Rgb, ""),
+            (Property::Flowing, "0"),
+            (Property::DelayOff, "15"),
+            (Property::MusicOn, ""),
+            (Property::Name, "living room"),
+            (Property::BgPower, "off"),
+            (Property::BgBright, "40"),
+            (Property::BgLmode, "3"),
+            (Property::BgHue, "120"),
+            (Property::BgSat, "50"),
+            (Property::BgFlowing, "1"),
+            (Property::BgFlowParams, "0,0,1000,1,16711680,100"),
+            (Property::NlBr, "10"),
+            (Property::ActiveMode, "1"),
+        ]))
+        .unwrap();
+
+        assert_eq!(state.power, Power::On);
+        assert_eq!(state.bright, 80);
+        assert_eq!(state.color_mode, Some(LightMode::ColorTemperature(4000)));
+        assert!(!state.flowing);
+        assert_eq!(state.delay_off, 15);
+        assert!(!state.music_on);
+        assert_eq!(state.name, "living room");
+        assert_eq!(
+            state.background,
+            Some(BackgroundState {
+                power: Power::Off,
+                bright: 40,
+                color_mode: LightMode::Hsv(HSV {
+                    hue: 120,
+                    saturation: 50
+                }),
+                flowing: true,
+                flow_params: Some("0,0,1000,1,16711680,100".to_string()),
+            })
+        );
+        assert_eq!(state.nl_br, Some(10));
+        assert_eq!(state.active_mode, Some(ActiveMode::Moonlight));
+    }
+
+    #[test]
+    fn missing_power_test() {
+        assert!(BulbState::parse(&values(&[(Property::Bright, "80")])).is_none());
+    }
+
+    #[test]
+    fn get_state_test() {
+        let mut result = vec![
+            "\"on\"",
+            "\"100\"",
+            "\"\"",
+            "\"16711680\"",
+            "\"\"",
+            "\"\"",
+            "\"1\"",
+        ];
+        let count = Property::into_enum_iter().count();
+        result.resize(count, "\"\"");
+        let reply = format!("{{\"id\":1, \"result\":[{}]}}\r\n", result.join(","));
+        let mock = ScriptedConnection::new(&[&reply]);
+        let mut conn = BulbConnection::from_connection(bulb_with_methods(&[Method::GetProp]), mock);
+
+        let state = conn.get_state().unwrap();
+        assert_eq!(state.power, Power::On);
+        assert_eq!(
+            state.color_mode,
+            Some(LightMode::Color(RGB { r: 255, g: 0, b: 0 }))
+        );
+        assert_eq!(state.background, None);
+
+        let written = conn.connection.lock().unwrap().written_str();
+        assert!(written.contains("\"params\":[\"power\",\"bright\",\"ct\""));
+    }
+}
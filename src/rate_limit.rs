@@ -0,0 +1,423 @@
+use std::{
+    convert::TryFrom,
+    io::{Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    connection::{BulbConnection, MethodCallError, StringVecResponse},
+    method::Method,
+    method_calls::MethodArg,
+};
+
+/// What a call does when the bulb's command quota is used up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitPolicy {
+    /// Sleep until the next command may be sent.
+    Block,
+    /// Return [`MethodCallError::RateLimited`] right away.
+    FailFast,
+    /// Hold brightness and colour changes back instead of sending them, and
+    /// return [`MethodCallError::Deferred`]. A newer change of the same kind
+    /// replaces the held one, so only the latest brightness and the latest
+    /// colour go out once the quota allows. Other calls block.
+    Coalesce,
+}
+
+/// Token bucket limiting the commands sent to one bulb. Every command takes a
+/// token, the bucket holds up to `burst` of them and earns one back every
+/// `interval`.
+///
+/// Yeelight bulbs drop the connection after about 60 commands a minute. The
+/// default stays below that even when starting with a full bucket: a burst of
+/// 5 and a token every 1.1 seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub interval: Duration,
+    pub policy: RateLimitPolicy,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            burst: 5,
+            interval: Duration::from_millis(1100),
+            policy: RateLimitPolicy::Block,
+        }
+    }
+}
+
+/// Changes that supersede each other when coalesced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CoalesceKey {
+    Bright,
+    Color,
+    BgBright,
+    BgColor,
+}
+
+impl CoalesceKey {
+    fn of(method: Method) -> Option<CoalesceKey> {
+        match method {
+            Method::SetBright => Some(CoalesceKey::Bright),
+            Method::SetRgb | Method::SetHsv | Method::SetCtAbx => Some(CoalesceKey::Color),
+            Method::BgSetBright => Some(CoalesceKey::BgBright),
+            Method::BgSetRgb | Method::BgSetHsv | Method::BgSetCtAbx => Some(CoalesceKey::BgColor),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    tokens: u32,
+    refilled_at: Instant,
+    deferred: Vec<(CoalesceKey, Method, Vec<MethodArg>)>,
+}
+
+impl RateLimiter {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        RateLimiter {
+            tokens: limit.burst,
+            refilled_at: now,
+            limit,
+            deferred: Vec::new(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if self.tokens >= self.limit.burst || self.limit.interval.is_zero() {
+            self.tokens = self.limit.burst;
+            self.refilled_at = now;
+            return;
+        }
+
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        let earned = (elapsed.as_nanos() / self.limit.interval.as_nanos()) as u32;
+        if earned == 0 {
+            return;
+        }
+        self.tokens = self.tokens.saturating_add(earned).min(self.limit.burst);
+        self.refilled_at = if self.tokens == self.limit.burst {
+            now
+        } else {
+            self.refilled_at + self.limit.interval * earned
+        };
+    }
+
+    /// Takes `count` tokens, or none of them and says how long to wait until
+    /// there are enough.
+    fn take(&mut self, count: u32, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        if self.tokens >= count {
+            self.tokens -= count;
+            return Ok(());
+        }
+
+        let missing = count - self.tokens;
+        let next = self.refilled_at + self.limit.interval * missing;
+        Err(next.saturating_duration_since(now))
+    }
+
+    /// Takes one token, sleeping until one is earned if need be.
+    fn take_blocking(&mut self) {
+        while let Err(wait) = self.take(1, Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+
+    fn defer(&mut self, key: CoalesceKey, method: Method, args: Vec<MethodArg>) {
+        self.deferred.retain(|(held, _, _)| *held != key);
+        self.deferred.push((key, method, args));
+    }
+
+    /// Drops the held back change `method` replaces. Held back changes go
+    /// out after the call that is let through, so they mustn't undo it.
+    fn supersede(&mut self, method: Method) {
+        if let Some(key) = CoalesceKey::of(method) {
+            self.deferred.retain(|(held, _, _)| *held != key);
+        }
+    }
+}
+
+impl<T: Read + Write> BulbConnection<T> {
+    /// Keeps the commands sent through this connection within `limit`.
+    /// Calls made while music mode is on skip the limiter, see
+    /// [`music_mode`](Self::music_mode).
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.limiter = Some(RateLimiter::new(limit, Instant::now()));
+        self
+    }
+
+    /// Number of brightness and colour changes held back by
+    /// [`RateLimitPolicy::Coalesce`].
+    pub fn deferred_len(&self) -> usize {
+        self.limiter.as_ref().map_or(0, |l| l.deferred.len())
+    }
+
+    /// Sends every held back change, waiting for the quota as needed.
+    /// Held back changes also go out on their own, after later calls,
+    /// whenever the quota has room for them. Errors are only reported here,
+    /// a held back change that fails to go out after another call is dropped.
+    pub fn flush_deferred(&mut self) -> Result<(), MethodCallError> {
+        self.send_deferred(true)
+    }
+
+    /// Lets a call through the limiter. Gives the arguments back when the
+    /// call may be sent now.
+    pub(crate) fn admit(
+        &mut self,
+        method: Method,
+        args: Vec<MethodArg>,
+    ) -> Result<Vec<MethodArg>, MethodCallError> {
+        let limiter = match self.limiter.as_mut() {
+            Some(limiter) => limiter,
+            None => return Ok(args),
+        };
+        if limiter.take(1, Instant::now()).is_ok() {
+            limiter.supersede(method);
+            return Ok(args);
+        }
+
+        match (limiter.limit.policy, CoalesceKey::of(method)) {
+            (RateLimitPolicy::FailFast, _) => Err(MethodCallError::RateLimited),
+            (RateLimitPolicy::Coalesce, Some(key)) => {
+                limiter.defer(key, method, args);
+                Err(MethodCallError::Deferred)
+            }
+            _ => {
+                limiter.take_blocking();
+                limiter.supersede(method);
+                Ok(args)
+            }
+        }
+    }
+
+    /// Takes a token for each of `count` requests sent as one batch.
+    pub(crate) fn admit_batch(&mut self, count: usize) -> Result<(), MethodCallError> {
+        let limiter = match self.limiter.as_mut() {
+            Some(limiter) if !self.music_mode => limiter,
+            _ => return Ok(()),
+        };
+
+        if limiter.limit.policy == RateLimitPolicy::FailFast {
+            let count = u32::try_from(count).map_err(|_| MethodCallError::RateLimited)?;
+            return limiter
+                .take(count, Instant::now())
+                .map_err(|_| MethodCallError::RateLimited);
+        }
+        for _ in 0..count {
+            limiter.take_blocking();
+        }
+        Ok(())
+    }
+
+    /// Sends held back changes while the quota has room for them, or until
+    /// they are all out when `wait` is set.
+    pub(crate) fn send_deferred(&mut self, wait: bool) -> Result<(), MethodCallError> {
+        loop {
+            let limiter = match self.limiter.as_mut() {
+                Some(limiter) if !limiter.deferred.is_empty() => limiter,
+                _ => return Ok(()),
+            };
+            if wait {
+                limiter.take_blocking();
+            } else if limiter.take(1, Instant::now()).is_err() {
+                return Ok(());
+            }
+
+            let (_, method, args) = limiter.deferred.remove(0);
+            self.send::<StringVecResponse>(method, args)
+                .map_err(|err| err.in_call(method, &self.bulb))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{RateLimit, RateLimitPolicy, RateLimiter};
+    use crate::{
+        connection::{BulbConnection, MethodCallError, MusicMode, TransitionMode},
+        method::Method,
+        rgb::RGB,
+        test_utils::{bulb_with_methods, ScriptedConnection},
+    };
+
+    fn limit(burst: u32, policy: RateLimitPolicy) -> RateLimit {
+        RateLimit {
+            burst,
+            interval: Duration::from_secs(60),
+            policy,
+        }
+    }
+
+    #[test]
+    fn token_bucket_test() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(
+            RateLimit {
+                burst: 2,
+                interval: Duration::from_secs(1),
+                policy: RateLimitPolicy::Block,
+            },
+            start,
+        );
+
+        assert_eq!(limiter.take(2, start), Ok(()));
+        assert_eq!(limiter.take(1, start), Err(Duration::from_secs(1)));
+        assert_eq!(
+            limiter.take(1, start + Duration::from_millis(400)),
+            Err(Duration::from_millis(600))
+        );
+        assert_eq!(limiter.take(1, start + Duration::from_millis(1500)), Ok(()));
+        // Half a second towards the next token was already earned.
+        assert_eq!(
+            limiter.take(1, start + Duration::from_millis(1500)),
+            Err(Duration::from_millis(500))
+        );
+        assert_eq!(limiter.take(2, start + Duration::from_secs(10)), Ok(()));
+    }
+
+    #[test]
+    fn fail_fast_test() {
+        let mock = ScriptedConnection::new(&["{\"id\":1, \"result\":[\"ok\"]}\r\n"]);
+        let mut conn = BulbConnection::from_connection(bulb_with_methods(&[Method::Toggle]), mock)
+            .with_rate_limit(limit(1, RateLimitPolicy::FailFast));
+
+        assert!(conn.toggle().is_ok());
+        assert!(matches!(
+            conn.toggle().unwrap_err().kind(),
+            MethodCallError::RateLimited
+        ));
+    }
+
+    #[test]
+    fn coalesce_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":2, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":3, \"result\":[\"ok\"]}\r\n",
+        ]);
+        let bulb = bulb_with_methods(&[Method::SetBright, Method::SetRgb]);
+        let mut conn = BulbConnection::from_connection(bulb, mock).with_rate_limit(RateLimit {
+            burst: 1,
+            interval: Duration::from_millis(10),
+            policy: RateLimitPolicy::Coalesce,
+        });
+
+        assert!(conn.set_bright(10, TransitionMode::Sudden).is_ok());
+        for bright in &[20, 30, 40] {
+            assert!(matches!(
+                conn.set_bright(*bright, TransitionMode::Sudden)
+                    .unwrap_err()
+                    .kind(),
+                MethodCallError::Deferred
+            ));
+        }
+        let red = RGB { r: 255, g: 0, b: 0 };
+        assert!(matches!(
+            conn.set_rgb(&red, TransitionMode::Sudden)
+                .unwrap_err()
+                .kind(),
+            MethodCallError::Deferred
+        ));
+        assert_eq!(conn.deferred_len(), 2);
+
+        conn.flush_deferred().unwrap();
+        assert_eq!(conn.deferred_len(), 0);
+
+        let written = conn.connection.lock().unwrap().written_str();
+        assert_eq!(written.lines().count(), 3);
+        assert!(written.contains("\"params\":[40,\"sudden\",50]"));
+        assert!(!written.contains("\"params\":[30"));
+        assert!(written.contains("\"set_rgb\""));
+    }
+
+    #[test]
+    fn failed_deferred_call_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":2, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":3, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":4, \"error\":{\"code\":-1, \"message\":\"general error\"}}\r\n",
+        ]);
+        let bulb = bulb_with_methods(&[Method::SetBright, Method::Toggle]);
+        let mut conn = BulbConnection::from_connection(bulb, mock).with_rate_limit(RateLimit {
+            burst: 2,
+            interval: Duration::from_millis(10),
+            policy: RateLimitPolicy::Coalesce,
+        });
+
+        assert!(conn.set_bright(10, TransitionMode::Sudden).is_ok());
+        assert!(conn.set_bright(15, TransitionMode::Sudden).is_ok());
+        assert!(matches!(
+            conn.set_bright(20, TransitionMode::Sudden)
+                .unwrap_err()
+                .kind(),
+            MethodCallError::Deferred
+        ));
+        thread::sleep(Duration::from_millis(30));
+
+        assert!(conn.toggle().is_ok());
+        assert_eq!(conn.deferred_len(), 0);
+        let written = conn.connection.lock().unwrap().written_str();
+        let toggle = written.find("\"toggle\"").unwrap();
+        assert!(written.find("\"params\":[20").unwrap() > toggle);
+    }
+
+    #[test]
+    fn superseded_deferred_call_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":2, \"result\":[\"ok\"]}\r\n",
+        ]);
+        let bulb = bulb_with_methods(&[Method::SetBright]);
+        let mut conn = BulbConnection::from_connection(bulb, mock).with_rate_limit(RateLimit {
+            burst: 1,
+            interval: Duration::from_millis(10),
+            policy: RateLimitPolicy::Coalesce,
+        });
+
+        assert!(conn.set_bright(10, TransitionMode::Sudden).is_ok());
+        assert!(conn.set_bright(20, TransitionMode::Sudden).is_err());
+        thread::sleep(Duration::from_millis(20));
+        assert!(conn.set_bright(30, TransitionMode::Sudden).is_ok());
+        conn.flush_deferred().unwrap();
+
+        let written = conn.connection.lock().unwrap().written_str();
+        assert_eq!(written.lines().count(), 2);
+        assert!(!written.contains("\"params\":[20"));
+    }
+
+    #[test]
+    fn music_mode_skips_limiter_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":2, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":3, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":4, \"result\":[\"ok\"]}\r\n",
+        ]);
+        let bulb = bulb_with_methods(&[Method::SetMusic, Method::Toggle]);
+        let mut conn = BulbConnection::from_connection(bulb, mock)
+            .with_rate_limit(limit(1, RateLimitPolicy::FailFast));
+
+        assert!(conn.set_music(MusicMode::On("127.0.0.1", 54321)).is_ok());
+        assert!(conn.music_mode());
+        assert!(conn.toggle().is_ok());
+        assert!(conn.toggle().is_ok());
+
+        assert!(conn.set_music(MusicMode::Off).is_ok());
+        assert!(!conn.music_mode());
+        assert!(matches!(
+            conn.toggle().unwrap_err().kind(),
+            MethodCallError::RateLimited
+        ));
+    }
+}
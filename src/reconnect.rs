@@ -0,0 +1,259 @@
+use std::{
+    io::{self, Read, Write},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    bulb::Bulb,
+    connection::{BulbConnection, ConnectionOptions, MethodCallError, TcpConnection},
+    event::BulbEvent,
+    frame::FrameBuffer,
+};
+
+/// Exponential backoff between reconnect attempts. The first retry waits
+/// `initial`, every following one `factor` times longer, capped at `max`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub factor: u32,
+
+    /// Attempts made before a call gives up and returns the IO error.
+    /// The next call starts a fresh round of attempts.
+    pub max_attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(10),
+            factor: 2,
+            max_attempts: 5,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before the given attempt, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let mut delay = self.initial;
+        for _ in 1..attempt {
+            delay = delay.saturating_mul(self.factor);
+            if delay >= self.max {
+                return self.max;
+            }
+        }
+        delay.min(self.max)
+    }
+}
+
+pub(crate) struct Reconnector<T> {
+    connect: Box<dyn FnMut() -> io::Result<T> + Send>,
+    backoff: Backoff,
+    broken: bool,
+}
+
+impl TcpConnection {
+    /// Like [`TcpConnection::with_options`], but reconnects to
+    /// `bulb.ip_address` with the same options whenever the stream breaks.
+    pub fn reconnecting(
+        bulb: Bulb,
+        options: &ConnectionOptions,
+        backoff: Backoff,
+    ) -> Result<Self, io::Error> {
+        let address = bulb.ip_address.clone();
+        let options = options.clone();
+        TcpConnection::with_options(bulb, &options)
+            .map(|conn| conn.with_reconnect(move || options.connect(&address), backoff))
+    }
+}
+
+impl<T: Read + Write> BulbConnection<T> {
+    /// Makes the connection replace its stream through `connect` when a
+    /// read or write fails, waiting between attempts as `backoff` says.
+    /// The failed call is sent again once on the new stream if its method
+    /// [is idempotent](crate::method::Method::is_idempotent), otherwise the
+    /// IO error is returned as the command may or may not have been applied.
+    /// Losing and regaining the stream is reported to
+    /// [`events`](Self::events) as [`BulbEvent::ConnectionLost`] and
+    /// [`BulbEvent::ConnectionRestored`].
+    pub fn with_reconnect(
+        mut self,
+        connect: impl FnMut() -> io::Result<T> + Send + 'static,
+        backoff: Backoff,
+    ) -> Self {
+        self.reconnector = Some(Reconnector {
+            connect: Box::new(connect),
+            backoff,
+            broken: false,
+        });
+        self
+    }
+
+    pub(crate) fn can_reconnect(&self) -> bool {
+        self.reconnector.is_some()
+    }
+
+    pub(crate) fn is_broken(&self) -> bool {
+        self.reconnector.as_ref().is_some_and(|r| r.broken)
+    }
+
+    /// Replaces the stream, retrying with backoff. Leaves the connection
+    /// marked broken when every attempt fails.
+    pub(crate) fn reconnect(&mut self) -> Result<(), MethodCallError> {
+        let reconnector = match self.reconnector.as_mut() {
+            Some(reconnector) => reconnector,
+            None => return Ok(()),
+        };
+
+        if !reconnector.broken {
+            reconnector.broken = true;
+            self.subscribers.emit(BulbEvent::ConnectionLost);
+        }
+
+        let mut last_error = io::Error::from(io::ErrorKind::NotConnected);
+        for attempt in 1..=reconnector.backoff.max_attempts {
+            thread::sleep(reconnector.backoff.delay(attempt));
+
+            match (reconnector.connect)() {
+                Ok(stream) => {
+                    reconnector.broken = false;
+                    self.subscribers.emit(BulbEvent::ConnectionRestored);
+
+                    let mut conn = self
+                        .connection
+                        .lock()
+                        .map_err(|_| MethodCallError::SynchronizationError)?;
+                    *conn = stream;
+                    self.frames = FrameBuffer::new();
+                    return Ok(());
+                }
+                Err(err) => last_error = err,
+            }
+        }
+
+        Err(MethodCallError::IOError(last_error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        io,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::Backoff;
+    use crate::{
+        connection::{BulbConnection, MethodCallError, TransitionMode},
+        event::BulbEvent,
+        method::Method,
+        test_utils::{bulb_with_methods, ScriptedConnection},
+    };
+
+    fn instant_backoff(max_attempts: u32) -> Backoff {
+        Backoff {
+            initial: Duration::ZERO,
+            max: Duration::ZERO,
+            factor: 2,
+            max_attempts,
+        }
+    }
+
+    /// Connects to the scripted streams in order. `None` and running out
+    /// of streams both refuse the connection.
+    fn connector(
+        streams: Vec<Option<ScriptedConnection>>,
+    ) -> impl FnMut() -> io::Result<ScriptedConnection> + Send + 'static {
+        let streams = Arc::new(Mutex::new(VecDeque::from(streams)));
+        move || {
+            streams
+                .lock()
+                .unwrap()
+                .pop_front()
+                .flatten()
+                .ok_or_else(|| io::ErrorKind::ConnectionRefused.into())
+        }
+    }
+
+    #[test]
+    fn backoff_delay_test() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(500),
+            factor: 2,
+            max_attempts: 10,
+        };
+
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(200));
+        assert_eq!(backoff.delay(3), Duration::from_millis(400));
+        assert_eq!(backoff.delay(4), Duration::from_millis(500));
+        assert_eq!(backoff.delay(40), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn idempotent_call_retried_test() {
+        let bulb = bulb_with_methods(&[Method::SetBright]);
+        let dead = ScriptedConnection::new(&[]);
+        let fresh = ScriptedConnection::new(&["{\"id\":1, \"result\":[\"ok\"]}\r\n"]);
+        let mut conn = BulbConnection::from_connection(bulb, dead)
+            .with_reconnect(connector(vec![Some(fresh)]), instant_backoff(3));
+        let events = conn.events();
+
+        let result = conn.set_bright(10, TransitionMode::Sudden).unwrap();
+        assert_eq!(result.result, vec!["ok"]);
+
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![BulbEvent::ConnectionLost, BulbEvent::ConnectionRestored]
+        );
+    }
+
+    #[test]
+    fn non_idempotent_call_not_retried_test() {
+        let bulb = bulb_with_methods(&[Method::Toggle]);
+        let dead = ScriptedConnection::new(&[]);
+        let fresh = ScriptedConnection::new(&["{\"id\":2, \"result\":[\"ok\"]}\r\n"]);
+        let mut conn = BulbConnection::from_connection(bulb, dead)
+            .with_reconnect(connector(vec![Some(fresh)]), instant_backoff(3));
+
+        assert!(matches!(
+            conn.toggle().unwrap_err().kind(),
+            MethodCallError::IOError(_)
+        ));
+        // The stream was replaced, so the next call goes through.
+        assert!(conn.toggle().is_ok());
+    }
+
+    #[test]
+    fn reconnect_gives_up_test() {
+        let bulb = bulb_with_methods(&[Method::SetBright]);
+        let dead = ScriptedConnection::new(&[]);
+        let fresh = ScriptedConnection::new(&["{\"id\":2, \"result\":[\"ok\"]}\r\n"]);
+        let connect = connector(vec![None, None, Some(fresh)]);
+        let mut conn =
+            BulbConnection::from_connection(bulb, dead).with_reconnect(connect, instant_backoff(2));
+        let events = conn.events();
+
+        assert!(matches!(
+            conn.set_bright(10, TransitionMode::Sudden)
+                .unwrap_err()
+                .kind(),
+            MethodCallError::IOError(_)
+        ));
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![BulbEvent::ConnectionLost]
+        );
+
+        // Still broken, so the next call reconnects before sending.
+        assert!(conn.is_broken());
+        assert!(conn.set_bright(10, TransitionMode::Sudden).is_ok());
+        assert!(!conn.is_broken());
+    }
+}
@@ -0,0 +1,265 @@
+//! Works out what a bulb shows while running a color flow, without a bulb.
+//! Steps fade linearly from the previous state to their target, colour
+//! temperatures are shown as their approximate RGB.
+
+use std::time::Duration;
+
+use crate::{
+    color::Kelvin,
+    connection::{CfAction, ColorFlow, FlowTupleMode},
+    power::Power,
+    rgb::RGB,
+};
+
+/// What a bulb shows at one moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LightState {
+    pub power: Power,
+    pub color: RGB,
+    pub bright: u8,
+}
+
+/// The state a step fades to, or None for a sleep.
+fn target(mode: &FlowTupleMode) -> Option<(RGB, u8)> {
+    match mode {
+        FlowTupleMode::Color(c) => Some((c.color, c.brightness)),
+        FlowTupleMode::Ct(c) => Some((RGB::from(Kelvin(c.ct)), c.brightness)),
+        FlowTupleMode::Sleep => None,
+    }
+}
+
+/// The state once the first `steps` steps of the sequence are done.
+fn after_steps(flow: &ColorFlow, begin: LightState, steps: usize) -> LightState {
+    flow.sequence[..steps]
+        .iter()
+        .filter_map(|tuple| target(&tuple.mode))
+        .fold(begin, |state, (color, bright)| LightState {
+            color,
+            bright,
+            ..state
+        })
+}
+
+fn mix(from: u8, to: u8, progress: f64) -> u8 {
+    (f64::from(from) + (f64::from(to) - f64::from(from)) * progress).round() as u8
+}
+
+/// How long the flow runs before its action is taken, or None if it runs
+/// until stopped.
+pub fn length(flow: &ColorFlow) -> Option<Duration> {
+    if flow.count == 0 || flow.sequence.is_empty() {
+        return None;
+    }
+    let steps = flow.sequence.iter().cycle().take(usize::from(flow.count));
+    Some(steps.map(|tuple| tuple.duration).sum())
+}
+
+/// What the bulb shows `at` into `flow`, started while showing `start`.
+/// The light is on while the flow runs. Once `count` steps are done the
+/// flow's action decides: back to `start`, stay on the last step, or off.
+pub fn render(flow: &ColorFlow, start: LightState, at: Duration) -> LightState {
+    let cycle: Duration = flow.sequence.iter().map(|tuple| tuple.duration).sum();
+    if cycle.is_zero() {
+        return start;
+    }
+    let running = LightState {
+        power: Power::On,
+        ..start
+    };
+    // Every pass after the first starts where the one before ended.
+    let looped = after_steps(flow, running, flow.sequence.len());
+
+    let len = flow.sequence.len() as u128;
+    let cycles = at.as_nanos() / cycle.as_nanos();
+    let mut into_cycle = at.as_nanos() % cycle.as_nanos();
+    let mut step = 0;
+    while into_cycle >= flow.sequence[step].duration.as_nanos() {
+        into_cycle -= flow.sequence[step].duration.as_nanos();
+        step += 1;
+    }
+
+    if flow.count != 0 && cycles * len + step as u128 >= u128::from(flow.count) {
+        let last = u128::from(flow.count) - 1;
+        let begin = if last < len { running } else { looped };
+        let end = after_steps(flow, begin, (last % len) as usize + 1);
+        return match flow.action {
+            CfAction::Recover => start,
+            CfAction::Stay => end,
+            CfAction::TurnOff => LightState {
+                power: Power::Off,
+                ..end
+            },
+        };
+    }
+
+    let begin = if cycles == 0 { running } else { looped };
+    let from = after_steps(flow, begin, step);
+    let tuple = &flow.sequence[step];
+    match target(&tuple.mode) {
+        Some((color, bright)) => {
+            let progress = into_cycle as f64 / tuple.duration.as_nanos() as f64;
+            LightState {
+                power: Power::On,
+                color: RGB {
+                    r: mix(from.color.r, color.r, progress),
+                    g: mix(from.color.g, color.g, progress),
+                    b: mix(from.color.b, color.b, progress),
+                },
+                bright: mix(from.bright, bright, progress),
+            }
+        }
+        None => from,
+    }
+}
+
+/// Renders `flow` at `fps` frames a second for `length`, starting at 0.
+/// Each frame comes with its time offset.
+pub fn timeline(
+    flow: &ColorFlow,
+    start: LightState,
+    fps: u32,
+    length: Duration,
+) -> Vec<(Duration, LightState)> {
+    if fps == 0 {
+        return Vec::new();
+    }
+    let frame = Duration::from_secs(1) / fps;
+    (0..)
+        .map(|i| frame * i)
+        .take_while(|at| *at < length)
+        .map(|at| (at, render(flow, start, at)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{length, render, timeline, LightState};
+    use crate::{
+        connection::{CfAction, ColorFlow},
+        power::Power,
+        rgb::RGB,
+    };
+
+    const WHITE: RGB = RGB {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+    const RED: RGB = RGB { r: 255, g: 0, b: 0 };
+    const BLUE: RGB = RGB { r: 0, g: 0, b: 255 };
+
+    fn start() -> LightState {
+        LightState {
+            power: Power::Off,
+            color: WHITE,
+            bright: 50,
+        }
+    }
+
+    fn red_blue(action: CfAction) -> ColorFlow {
+        ColorFlow::builder()
+            .color(RED, 100, 1000)
+            .color(BLUE, 20, 1000)
+            .then(action)
+            .build()
+            .unwrap()
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn render_test() {
+        let flow = red_blue(CfAction::Recover);
+        let shown = |millis| render(&flow, start(), ms(millis));
+
+        assert_eq!(
+            shown(0),
+            LightState {
+                power: Power::On,
+                ..start()
+            }
+        );
+        assert_eq!(
+            shown(500),
+            LightState {
+                power: Power::On,
+                color: RGB {
+                    r: 255,
+                    g: 128,
+                    b: 128
+                },
+                bright: 75,
+            }
+        );
+        assert_eq!(shown(1000).color, RED);
+        assert_eq!(
+            shown(1500).color,
+            RGB {
+                r: 128,
+                g: 0,
+                b: 128
+            }
+        );
+        assert_eq!(shown(1500).bright, 60);
+        assert_eq!(shown(2000), start());
+        assert_eq!(length(&flow), Some(ms(2000)));
+    }
+
+    #[test]
+    fn end_action_test() {
+        let stay = render(&red_blue(CfAction::Stay), start(), ms(5000));
+        assert_eq!((stay.power, stay.color, stay.bright), (Power::On, BLUE, 20));
+
+        let off = render(&red_blue(CfAction::TurnOff), start(), ms(5000));
+        assert_eq!((off.power, off.color), (Power::Off, BLUE));
+
+        // Three steps end part way into the second pass.
+        let mut flow = red_blue(CfAction::Stay);
+        flow.count = 3;
+        assert_eq!(
+            render(&flow, start(), ms(2500)).color,
+            RGB {
+                r: 128,
+                g: 0,
+                b: 128
+            }
+        );
+        assert_eq!(render(&flow, start(), ms(3000)).color, RED);
+    }
+
+    #[test]
+    fn loop_and_sleep_test() {
+        let flow = ColorFlow::builder()
+            .color(RED, 100, 1000)
+            .sleep(1000)
+            .color(BLUE, 100, 1000)
+            .forever()
+            .build()
+            .unwrap();
+        assert_eq!(length(&flow), None);
+
+        assert_eq!(render(&flow, start(), ms(1500)).color, RED);
+        // Later passes fade from blue, where the last one ended.
+        assert_eq!(
+            render(&flow, start(), ms(3500)).color,
+            RGB {
+                r: 128,
+                g: 0,
+                b: 128
+            }
+        );
+        assert_eq!(render(&flow, start(), ms(3_000_000_000)).color, BLUE);
+    }
+
+    #[test]
+    fn timeline_test() {
+        let frames = timeline(&red_blue(CfAction::Recover), start(), 2, ms(2000));
+        let times: Vec<Duration> = frames.iter().map(|(at, _)| *at).collect();
+        assert_eq!(times, vec![ms(0), ms(500), ms(1000), ms(1500)]);
+        assert_eq!(frames[2].1.color, RED);
+    }
+}
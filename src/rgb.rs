@@ -12,14 +12,26 @@ impl From<u32> for RGB {
         RGB {
             r: ((int >> 16) & 0xFF) as u8,
             g: ((int >> 8) & 0xFF) as u8,
-            b: ((int >> 0) & 0xFF) as u8,
+            b: (int & 0xFF) as u8,
         }
     }
 }
 
 impl From<&RGB> for u32 {
     fn from(rgb: &RGB) -> Self {
-        return 65536 * (rgb.r as u32) + 256 * (rgb.g as u32) + (rgb.b as u32);
+        65536 * (rgb.r as u32) + 256 * (rgb.g as u32) + (rgb.b as u32)
+    }
+}
+
+/// `255, 136, 0`, or `#ff8800` with `{:#}`. See also
+/// [`to_css`](RGB::to_css).
+impl fmt::Display for RGB {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            f.write_str(&self.to_hex())
+        } else {
+            write!(f, "{}, {}, {}", self.r, self.g, self.b)
+        }
     }
 }
 
@@ -44,9 +56,3 @@ mod tests {
         );
     }
 }
-
-impl fmt::Display for RGB {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}, {}, {}", self.r, self.g, self.b)
-    }
-}
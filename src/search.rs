@@ -1,8 +1,9 @@
 use crate::bulb::Bulb;
-use std::io::Read;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket};
 use std::time::Duration;
-use std::{io, str, thread, time};
+use std::{io, str, time};
 
 const MULTICAST_ADDR: &str = "239.255.255.250:1982";
 
@@ -19,15 +20,15 @@ trait SendRecvable {
 
 impl SendRecvable for UdpSocket {
     fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> io::Result<usize> {
-        self.send_to(buf, addr)
+        UdpSocket::send_to(self, buf, addr)
     }
 
     fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
-        self.recv_from(buf)
+        UdpSocket::recv_from(self, buf)
     }
 
     fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
-        self.set_read_timeout(dur)
+        UdpSocket::set_read_timeout(self, dur)
     }
 }
 
@@ -35,8 +36,7 @@ impl BulbSearcher {
     pub fn search(&self) -> Option<Vec<Bulb>> {
         UdpSocket::bind("0.0.0.0:34254")
             .ok()
-            .map(|s| self.search_with_socket(s))
-            .flatten()
+            .and_then(|s| self.search_with_socket(s))
     }
 
     fn search_with_socket<T: SendRecvable>(&self, mut socket: T) -> Option<Vec<Bulb>> {
@@ -51,7 +51,7 @@ impl BulbSearcher {
         }
 
         if let BulbSearcher::UntilDuration(d) = self {
-            socket.set_read_timeout(Some(*d));
+            let _ = socket.set_read_timeout(Some(*d));
         }
 
         let start = time::Instant::now();
@@ -59,17 +59,13 @@ impl BulbSearcher {
         let mut buf = [0; 2048];
         let mut found_bulbs: Vec<Bulb> = Vec::new();
         loop {
-            socket.recv_from(&mut buf).ok().map(|_| {
-                str::from_utf8(&buf)
-                    .ok()
-                    .map(|s| Bulb::parse(s))
-                    .flatten()
-                    .map(|bulb| {
-                        if !found_bulbs.iter().any(|b| b.id == bulb.id) {
-                            found_bulbs.push(bulb)
-                        }
-                    });
-            });
+            if socket.recv_from(&mut buf).is_ok() {
+                if let Some(bulb) = str::from_utf8(&buf).ok().and_then(Bulb::parse) {
+                    if !found_bulbs.iter().any(|b| b.id == bulb.id) {
+                        found_bulbs.push(bulb)
+                    }
+                }
+            }
 
             match self {
                 BulbSearcher::UntilDuration(duration_limit) => {
@@ -88,65 +84,160 @@ impl BulbSearcher {
     }
 }
 
-struct MockSendRecvable<'a> {
-    send_to_result: usize,
-    recv_contents: &'a [u8],
-    recv_delay: Option<Duration>,
-    recv_timeout: Option<Duration>,
+/// A bulb heard announcing itself.
+#[derive(Debug, Clone)]
+pub struct Announcement {
+    pub bulb: Bulb,
+    /// True the first time the listener hears from the bulb, false when it
+    /// announces itself again.
+    pub new: bool,
 }
 
-impl<'a> SendRecvable for MockSendRecvable<'a> {
-    fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> io::Result<usize> {
-        io::Result::Ok(self.send_to_result)
+/// The bulb in an `ssdp:alive` notification, None for any other message.
+fn parse_notify(message: &str) -> Option<Bulb> {
+    let mut lines = message.split("\r\n");
+    if !lines.next()?.starts_with("NOTIFY ") {
+        return None;
     }
+    let alive = lines.any(|line| {
+        line.split_once(':').is_some_and(|(key, value)| {
+            key.trim().eq_ignore_ascii_case("nts") && value.trim() == "ssdp:alive"
+        })
+    });
+    if alive {
+        Bulb::parse(message)
+    } else {
+        None
+    }
+}
 
-    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
-        if let Some(delay) = self.recv_delay {
-            if let Some(timeout) = self.recv_timeout {
-                if delay > timeout {
-                    thread::sleep(timeout);
-                    return io::Result::Ok((
-                        0,
-                        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080),
-                    ));
-                }
-                thread::sleep(delay);
-            } else {
-                thread::sleep(delay);
-            }
-        }
+/// Listens for the `NOTIFY` messages bulbs multicast when they come online
+/// and then every hour, so bulbs are found without searching for them.
+pub struct BulbListener {
+    socket: UdpSocket,
+    seen: HashSet<String>,
+}
 
-        for (i, elem) in buf.iter_mut().enumerate() {
-            if i >= self.recv_contents.len() {
-                break;
-            };
-            *elem = self.recv_contents[i];
+impl BulbListener {
+    /// Joins the multicast group on all interfaces.
+    pub fn bind() -> io::Result<Self> {
+        BulbListener::bind_on(Ipv4Addr::UNSPECIFIED)
+    }
+
+    /// Joins the multicast group on the interface with address `interface`.
+    /// The port is shared, so several listeners can run on a host, each
+    /// getting every announcement.
+    pub fn bind_on(interface: Ipv4Addr) -> io::Result<Self> {
+        let group: SocketAddrV4 = MULTICAST_ADDR
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(all(
+            unix,
+            not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))
+        ))]
+        socket.set_reuse_port(true)?;
+        socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, group.port()).into())?;
+
+        let socket = UdpSocket::from(socket);
+        socket.join_multicast_v4(group.ip(), &interface)?;
+        Ok(BulbListener::from_socket(socket))
+    }
+
+    fn from_socket(socket: UdpSocket) -> Self {
+        BulbListener {
+            socket,
+            seen: HashSet::new(),
         }
+    }
 
-        io::Result::Ok((
-            buf.len(),
-            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080),
-        ))
+    /// How long [`next_announcement`](Self::next_announcement) waits, or
+    /// forever if None.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_read_timeout(timeout)
     }
 
-    fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
-        self.recv_timeout = dur;
-        io::Result::Ok(())
+    /// Waits for the next bulb to announce itself, skipping any other
+    /// messages. Fails if the socket does, including when the read timeout
+    /// passes.
+    pub fn next_announcement(&mut self) -> io::Result<Announcement> {
+        let mut buf = [0; 2048];
+        loop {
+            let (len, _) = self.socket.recv_from(&mut buf)?;
+            if let Some(bulb) = str::from_utf8(&buf[..len]).ok().and_then(parse_notify) {
+                let new = self.seen.insert(bulb.id.clone());
+                return Ok(Announcement { bulb, new });
+            }
+        }
+    }
+
+    /// Announcements as they arrive, never ending on its own.
+    pub fn announcements(&mut self) -> impl Iterator<Item = io::Result<Announcement>> + '_ {
+        std::iter::from_fn(move || Some(self.next_announcement()))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::{
+        io,
+        net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
         ops::{Div, Mul},
+        thread,
         time::Duration,
     };
 
-    use crate::search::MockSendRecvable;
+    use super::{parse_notify, BulbListener, BulbSearcher, SendRecvable};
+
+    struct MockSendRecvable<'a> {
+        send_to_result: usize,
+        recv_contents: &'a [u8],
+        recv_delay: Option<Duration>,
+        recv_timeout: Option<Duration>,
+    }
 
-    use super::BulbSearcher;
+    impl SendRecvable for MockSendRecvable<'_> {
+        fn send_to<A: ToSocketAddrs>(&self, _buf: &[u8], _addr: A) -> io::Result<usize> {
+            io::Result::Ok(self.send_to_result)
+        }
 
-    const recv_contents: &str = concat!(
+        fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            if let Some(delay) = self.recv_delay {
+                if let Some(timeout) = self.recv_timeout {
+                    if delay > timeout {
+                        thread::sleep(timeout);
+                        return io::Result::Ok((
+                            0,
+                            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080),
+                        ));
+                    }
+                    thread::sleep(delay);
+                } else {
+                    thread::sleep(delay);
+                }
+            }
+
+            for (i, elem) in buf.iter_mut().enumerate() {
+                if i >= self.recv_contents.len() {
+                    break;
+                };
+                *elem = self.recv_contents[i];
+            }
+
+            io::Result::Ok((
+                buf.len(),
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080),
+            ))
+        }
+
+        fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
+            self.recv_timeout = dur;
+            io::Result::Ok(())
+        }
+    }
+
+    const RECV_CONTENTS: &str = concat!(
         "HTTP/1.1 200 OK\r\n",
         "Cache-Control: max-age=3600\r\n",
         "Date: \r\n",
@@ -171,7 +262,7 @@ mod tests {
     fn bulb_search_count_one_test() {
         let mock = MockSendRecvable {
             send_to_result: 0,
-            recv_contents: recv_contents.as_bytes(),
+            recv_contents: RECV_CONTENTS.as_bytes(),
             recv_delay: None,
             recv_timeout: None,
         };
@@ -190,14 +281,14 @@ mod tests {
         let return_duration = Duration::from_millis(10);
         let mock = MockSendRecvable {
             send_to_result: 0,
-            recv_contents: recv_contents.as_bytes(),
+            recv_contents: RECV_CONTENTS.as_bytes(),
             recv_delay: Some(return_duration),
             recv_timeout: None,
         };
 
         let search_result = BulbSearcher::UntilDuration(return_duration.div(2))
             .search_with_socket(mock)
-            .unwrap_or(Vec::new());
+            .unwrap_or_default();
 
         assert!(search_result.is_empty());
     }
@@ -207,7 +298,7 @@ mod tests {
         let return_duration = Duration::from_millis(10);
         let mock = MockSendRecvable {
             send_to_result: 0,
-            recv_contents: recv_contents.as_bytes(),
+            recv_contents: RECV_CONTENTS.as_bytes(),
             recv_delay: Some(return_duration),
             recv_timeout: None,
         };
@@ -220,4 +311,95 @@ mod tests {
 
         assert_eq!(bulb.id, "0x000000000015243f".to_string())
     }
+
+    fn notify(id: &str, nts: &str) -> String {
+        format!(
+            concat!(
+                "NOTIFY * HTTP/1.1\r\n",
+                "Host: 239.255.255.250:1982\r\n",
+                "Cache-Control: max-age=3600\r\n",
+                "Location: yeelight://192.168.1.239:55443\r\n",
+                "NTS: {}\r\n",
+                "Server: POSIX UPnP/1.0 YGLC/1\r\n",
+                "id: {}\r\n",
+                "model: color\r\n",
+                "fw_ver: 18\r\n",
+                "support: get_prop set_default set_power toggle\r\n",
+                "power: on\r\n",
+                "bright: 100\r\n",
+                "color_mode: 2\r\n",
+                "ct: 4000\r\n",
+                "rgb: 16711680\r\n",
+                "hue: 100\r\n",
+                "sat: 35\r\n",
+                "name: my_bulb\r\n",
+            ),
+            nts, id
+        )
+    }
+
+    #[test]
+    fn parse_notify_test() {
+        let bulb = parse_notify(&notify("0x000000000015243f", "ssdp:alive")).unwrap();
+        assert_eq!(bulb.id, "0x000000000015243f");
+        assert_eq!(bulb.ip_address, "192.168.1.239:55443");
+
+        assert!(parse_notify(&notify("0x1", "ssdp:byebye")).is_none());
+        // Search responses are only for the bulb searcher.
+        assert!(parse_notify(RECV_CONTENTS).is_none());
+    }
+
+    #[test]
+    fn shared_port_test() {
+        let first = BulbListener::bind_on(Ipv4Addr::LOCALHOST).unwrap();
+        let second = BulbListener::bind_on(Ipv4Addr::LOCALHOST).unwrap();
+        assert_eq!(
+            first.socket.local_addr().unwrap(),
+            second.socket.local_addr().unwrap()
+        );
+    }
+
+    #[test]
+    fn bulb_listener_test() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut listener = BulbListener::from_socket(socket);
+        listener
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let bulb = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        for message in [
+            notify("0x1", "ssdp:alive"),
+            RECV_CONTENTS.to_string(),
+            notify("0x2", "ssdp:alive"),
+            notify("0x1", "ssdp:alive"),
+        ]
+        .iter()
+        {
+            bulb.send_to(message.as_bytes(), addr).unwrap();
+        }
+
+        let heard: Vec<(String, bool)> = listener
+            .announcements()
+            .take(3)
+            .map(|announcement| {
+                let announcement = announcement.unwrap();
+                (announcement.bulb.id, announcement.new)
+            })
+            .collect();
+        assert_eq!(
+            heard,
+            vec![
+                ("0x1".to_string(), true),
+                ("0x2".to_string(), true),
+                ("0x1".to_string(), false),
+            ]
+        );
+
+        listener
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        assert!(listener.next_announcement().is_err());
+    }
 }
@@ -0,0 +1,245 @@
+//! Hand written serde representations for the types whose derived form
+//! wouldn't be friendly to read or write by hand.
+
+use std::{convert::TryFrom, fmt};
+
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{method::Method, rgb::RGB};
+
+/// Durations as a whole number of milliseconds, the unit the bulb uses.
+pub(crate) mod millis {
+    use std::{convert::TryFrom, time::Duration};
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let millis = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        serializer.serialize_u64(millis)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+/// RGB as `"#ff8800"`.
+impl Serialize for RGB {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+struct RgbVisitor;
+
+impl<'de> Visitor<'de> for RgbVisitor {
+    type Value = RGB;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a colour as \"#rrggbb\" or \"#rgb\"")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<RGB, E> {
+        RGB::from_hex(value).map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+}
+
+impl<'de> Deserialize<'de> for RGB {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<RGB, D::Error> {
+        deserializer.deserialize_str(RgbVisitor)
+    }
+}
+
+/// Methods by their wire name, such as `"set_rgb"`.
+impl Serialize for Method {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name: &str = self.into();
+        serializer.serialize_str(name)
+    }
+}
+
+struct MethodVisitor;
+
+impl<'de> Visitor<'de> for MethodVisitor {
+    type Value = Method;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a method name such as \"set_rgb\"")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Method, E> {
+        Method::try_from(value).map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+}
+
+impl<'de> Deserialize<'de> for Method {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Method, D::Error> {
+        deserializer.deserialize_str(MethodVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use serde_json::json;
+
+    use crate::{
+        bulb::Bulb,
+        connection::{
+            CfAction, ColorFlow, ColorFlowTupleMode, Cron, CronType, FlowTuple, FlowTupleMode,
+            Scene, SceneBuf, TransitionMode,
+        },
+        lightmode::{LightMode, HSV},
+        method::Method,
+        power::Power,
+        rgb::RGB,
+    };
+
+    #[test]
+    fn rgb_test() {
+        let orange = RGB {
+            r: 255,
+            g: 136,
+            b: 0,
+        };
+        assert_eq!(serde_json::to_value(orange).unwrap(), json!("#ff8800"));
+        assert_eq!(
+            serde_json::from_value::<RGB>(json!("#FF8800")).unwrap(),
+            orange
+        );
+
+        for bad in ["ff8800", "#ff880", "#ff88zz", "#ff88001"].iter() {
+            assert!(serde_json::from_value::<RGB>(json!(bad)).is_err());
+        }
+    }
+
+    #[test]
+    fn enum_tags_test() {
+        assert_eq!(serde_json::to_value(Power::On).unwrap(), json!("on"));
+        assert_eq!(
+            serde_json::to_value(Method::SetRgb).unwrap(),
+            json!("set_rgb")
+        );
+        assert_eq!(
+            serde_json::from_value::<Method>(json!("bg_set_power")).unwrap(),
+            Method::BgSetPower
+        );
+        assert_eq!(
+            serde_json::to_value(TransitionMode::Sudden).unwrap(),
+            json!("sudden")
+        );
+        assert_eq!(
+            serde_json::to_value(TransitionMode::Smooth(Duration::from_millis(500))).unwrap(),
+            json!({ "smooth": 500 })
+        );
+        assert_eq!(
+            serde_json::to_value(LightMode::ColorTemperature(4000)).unwrap(),
+            json!({ "ct": 4000 })
+        );
+        assert_eq!(
+            serde_json::from_value::<LightMode>(json!({ "hsv": { "hue": 120, "saturation": 50 } }))
+                .unwrap(),
+            LightMode::Hsv(HSV {
+                hue: 120,
+                saturation: 50
+            })
+        );
+    }
+
+    #[test]
+    fn flow_and_scene_test() {
+        let flow = ColorFlow {
+            count: 0,
+            action: CfAction::TurnOff,
+            sequence: vec![
+                FlowTuple {
+                    duration: Duration::from_millis(1000),
+                    mode: FlowTupleMode::Color(ColorFlowTupleMode {
+                        color: RGB { r: 255, g: 0, b: 0 },
+                        brightness: 100,
+                    }),
+                },
+                FlowTuple {
+                    duration: Duration::from_millis(500),
+                    mode: FlowTupleMode::Sleep,
+                },
+            ],
+        };
+        let expected = json!({
+            "count": 0,
+            "action": "turn_off",
+            "sequence": [
+                { "duration": 1000, "type": "color", "color": "#ff0000", "brightness": 100 },
+                { "duration": 500, "type": "sleep" },
+            ],
+        });
+        assert_eq!(serde_json::to_value(&flow).unwrap(), expected);
+
+        let parsed: ColorFlow = serde_json::from_value(expected.clone()).unwrap();
+        assert_eq!(
+            serde_json::to_value(Scene::Cf(&parsed)).unwrap(),
+            json!({ "cf": expected })
+        );
+        assert_eq!(
+            serde_json::to_value(Scene::Color(&RGB { r: 0, g: 255, b: 0 }, 70)).unwrap(),
+            json!({ "color": ["#00ff00", 70] })
+        );
+
+        // Stored scenes read back into the owned form, which writes the same.
+        for value in [
+            json!({ "cf": expected }),
+            json!({ "color": ["#00ff00", 70] }),
+            json!({ "hsv": [{ "hue": 120, "saturation": 50 }, 40] }),
+            json!({ "ct": [2700, 30] }),
+            json!({ "auto_delay_off": [50, 5] }),
+        ]
+        .iter()
+        {
+            let scene: SceneBuf = serde_json::from_value(value.clone()).unwrap();
+            assert_eq!(serde_json::to_value(&scene).unwrap(), *value);
+            assert_eq!(serde_json::to_value(scene.as_scene()).unwrap(), *value);
+        }
+
+        let cron = Cron {
+            cron_type: CronType::PowerOff,
+            minutes: 15,
+        };
+        assert_eq!(
+            serde_json::to_value(&cron).unwrap(),
+            json!({ "cron_type": "power_off", "minutes": 15 })
+        );
+    }
+
+    #[test]
+    fn bulb_round_trip_test() {
+        let bulb = Bulb {
+            id: "0x000000000015243f".to_string(),
+            model: "color".to_string(),
+            fw_ver: "18".to_string(),
+            support: [Method::SetPower].iter().copied().collect::<HashSet<_>>(),
+            power: Power::On,
+            bright: 100,
+            color_mode: LightMode::Color(RGB { r: 0, g: 0, b: 255 }),
+            name: "Kitchen".to_string(),
+            ip_address: "192.168.1.239:55443".to_string(),
+        };
+
+        let value = serde_json::to_value(&bulb).unwrap();
+        assert_eq!(value["support"], json!(["set_power"]));
+        assert_eq!(value["color_mode"], json!({ "color": "#0000ff" }));
+
+        let parsed: Bulb = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.id, bulb.id);
+        assert_eq!(parsed.support, bulb.support);
+        assert_eq!(parsed.color_mode, bulb.color_mode);
+    }
+}
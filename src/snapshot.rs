@@ -0,0 +1,500 @@
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+};
+
+use crate::{
+    connection::{
+        BulbConnection, ColorFlow, MethodCallError, PowerMode, Scene, StringVecResponse,
+        TransitionMode,
+    },
+    lightmode::LightMode,
+    method::Method,
+    method_calls::{bright_args, ct_abx_args, hsv_args, power_args, rgb_args, MethodArg},
+    model::{Capabilities, BACKGROUND},
+    power::Power,
+    property::{ActiveMode, BackgroundState, BulbState},
+};
+
+/// The state of one light of a bulb, as far as it can be put back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LightSnapshot {
+    pub power: Power,
+    pub bright: u8,
+
+    // None for lights without colour or colour temperature control.
+    pub color_mode: Option<LightMode>,
+
+    // The running color flow as `flow_params` reports it, `count,action`
+    // followed by the flow expression. None when no flow is running.
+    pub flow_params: Option<String>,
+
+    // Whether a ceiling lamp had its moonlight on, and how bright. None for
+    // lights without one.
+    pub active_mode: Option<ActiveMode>,
+    pub nl_br: Option<u8>,
+}
+
+/// Everything needed to put a bulb back the way it was, captured with
+/// [`BulbConnection::snapshot`] and put back with [`BulbConnection::restore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    pub main: LightSnapshot,
+
+    // The background light of lamps that have one.
+    pub background: Option<LightSnapshot>,
+}
+
+impl From<&BulbState> for Snapshot {
+    fn from(state: &BulbState) -> Self {
+        Snapshot {
+            main: LightSnapshot {
+                power: state.power,
+                bright: state.bright,
+                color_mode: state.color_mode.clone(),
+                flow_params: state.flow_params.clone().filter(|_| state.flowing),
+                active_mode: state.active_mode,
+                nl_br: state.nl_br,
+            },
+            background: state.background.as_ref().map(LightSnapshot::from),
+        }
+    }
+}
+
+impl From<&BackgroundState> for LightSnapshot {
+    fn from(state: &BackgroundState) -> Self {
+        LightSnapshot {
+            power: state.power,
+            bright: state.bright,
+            color_mode: Some(state.color_mode.clone()),
+            flow_params: state.flow_params.clone().filter(|_| state.flowing),
+            active_mode: None,
+            nl_br: None,
+        }
+    }
+}
+
+/// The methods that control one of a bulb's lights.
+struct LightMethods {
+    set_scene: Method,
+    set_power: Method,
+    start_cf: Method,
+    set_rgb: Method,
+    set_hsv: Method,
+    set_ct_abx: Method,
+    set_bright: Method,
+}
+
+const MAIN_LIGHT: LightMethods = LightMethods {
+    set_scene: Method::SetScene,
+    set_power: Method::SetPower,
+    start_cf: Method::StartCf,
+    set_rgb: Method::SetRgb,
+    set_hsv: Method::SetHsv,
+    set_ct_abx: Method::SetCtAbx,
+    set_bright: Method::SetBright,
+};
+
+const BACKGROUND_LIGHT: LightMethods = LightMethods {
+    set_scene: Method::BgSetScene,
+    set_power: Method::BgSetPower,
+    start_cf: Method::BgStartCf,
+    set_rgb: Method::BgSetRgb,
+    set_hsv: Method::BgSetHsv,
+    set_ct_abx: Method::BgSetCtAbx,
+    set_bright: Method::BgSetBright,
+};
+
+/// Turns `flow_params` into the `start_cf` arguments: count, action and
+/// the flow expression. None if it isn't a flow the bulb would accept back.
+fn flow_args(flow_params: &str, capabilities: &Capabilities) -> Option<Vec<MethodArg>> {
+    flow_params
+        .parse::<ColorFlow>()
+        .ok()?
+        .params(capabilities)
+        .ok()
+}
+
+type Call = (Method, Result<Vec<MethodArg>, MethodCallError>);
+
+/// The calls that put one light back. A single `set_scene` does it when the
+/// bulb supports it, as it sets power, colour and brightness in one go. A
+/// lamp in moonlight is turned on in night light mode at its `nl_br` instead.
+/// `capabilities` are what the light takes, see
+/// [`Bulb::capabilities`](crate::bulb::Bulb::capabilities).
+fn restore_calls(
+    light: &LightSnapshot,
+    methods: &LightMethods,
+    support: &HashSet<Method>,
+    capabilities: &Capabilities,
+) -> Vec<Call> {
+    let sudden = TransitionMode::Sudden;
+    if light.power == Power::Off {
+        return vec![(
+            methods.set_power,
+            power_args(Power::Off, capabilities, sudden, None),
+        )];
+    }
+
+    if light.active_mode == Some(ActiveMode::Moonlight) {
+        let night_light = Some(PowerMode::NightLight);
+        let mut calls = vec![(
+            methods.set_power,
+            power_args(Power::On, capabilities, sudden, night_light),
+        )];
+        calls.extend(
+            light
+                .nl_br
+                .map(|nl_br| (methods.set_bright, bright_args(nl_br, sudden))),
+        );
+        return calls;
+    }
+
+    let flow = light
+        .flow_params
+        .as_deref()
+        .and_then(|flow| flow_args(flow, capabilities));
+    if support.contains(&methods.set_scene) {
+        let scene = match (&flow, &light.color_mode) {
+            (Some(args), _) => {
+                let mut scene = vec![MethodArg::String("cf".to_string())];
+                scene.extend(args.iter().cloned());
+                Some(Ok(scene))
+            }
+            (None, Some(LightMode::Color(rgb))) => {
+                Some(Scene::Color(rgb, light.bright).params(capabilities))
+            }
+            (None, Some(LightMode::Hsv(hsv))) => {
+                Some(Scene::HSV(hsv, light.bright).params(capabilities))
+            }
+            (None, Some(LightMode::ColorTemperature(ct))) => {
+                Some(Scene::Ct(*ct, light.bright).params(capabilities))
+            }
+            (None, None) => None,
+        };
+        if let Some(args) = scene {
+            return vec![(methods.set_scene, args)];
+        }
+    }
+
+    let power_on = (
+        methods.set_power,
+        power_args(Power::On, capabilities, sudden, None),
+    );
+    let colour = match (flow, &light.color_mode) {
+        // The flow sets the brightness itself.
+        (Some(args), _) => return vec![power_on, (methods.start_cf, Ok(args))],
+        (None, Some(LightMode::Color(rgb))) => {
+            Some((methods.set_rgb, rgb_args(rgb, capabilities, sudden)))
+        }
+        (None, Some(LightMode::Hsv(hsv))) => {
+            Some((methods.set_hsv, hsv_args(hsv, capabilities, sudden)))
+        }
+        (None, Some(LightMode::ColorTemperature(ct))) => {
+            Some((methods.set_ct_abx, ct_abx_args(*ct, capabilities, sudden)))
+        }
+        (None, None) => None,
+    };
+
+    let mut calls = vec![power_on];
+    calls.extend(colour);
+    calls.push((methods.set_bright, bright_args(light.bright, sudden)));
+    calls
+}
+
+impl<C: Read + Write> BulbConnection<C> {
+    /// Captures the bulb's state with a single `get_prop`.
+    pub fn snapshot(&mut self) -> Result<Snapshot, MethodCallError> {
+        self.get_state().map(|state| Snapshot::from(&state))
+    }
+
+    /// Puts the bulb back the way `snapshot` found it, with as few commands
+    /// as it takes: one `set_scene` per light where the bulb supports it,
+    /// otherwise power, colour and brightness one by one. A light that was
+    /// off is just turned off. Stops at the first call that fails.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), MethodCallError> {
+        let mut calls = restore_calls(
+            &snapshot.main,
+            &MAIN_LIGHT,
+            &self.bulb.support,
+            &self.bulb.capabilities(),
+        );
+        if let Some(background) = &snapshot.background {
+            calls.extend(restore_calls(
+                background,
+                &BACKGROUND_LIGHT,
+                &self.bulb.support,
+                &BACKGROUND,
+            ));
+        }
+
+        for (method, args) in calls {
+            self.call_method::<StringVecResponse>(method, args)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+pub use presets::Presets;
+
+#[cfg(feature = "serde")]
+mod presets {
+    use std::{
+        collections::BTreeMap,
+        fs,
+        io::{self, ErrorKind},
+        path::{Path, PathBuf},
+    };
+
+    use super::Snapshot;
+
+    /// Named snapshots kept in a JSON file, such as `"evening"` or
+    /// `"before guests"`. Changes are only written when [`save`](Self::save)
+    /// is called.
+    #[derive(Debug)]
+    pub struct Presets {
+        path: PathBuf,
+        presets: BTreeMap<String, Snapshot>,
+    }
+
+    impl Presets {
+        /// Reads the presets stored at `path`. A missing file holds none.
+        pub fn open(path: impl AsRef<Path>) -> io::Result<Presets> {
+            let path = path.as_ref().to_path_buf();
+            let presets = match fs::read(&path) {
+                Ok(bytes) => serde_json::from_slice(&bytes)?,
+                Err(err) if err.kind() == ErrorKind::NotFound => BTreeMap::new(),
+                Err(err) => return Err(err),
+            };
+            Ok(Presets { path, presets })
+        }
+
+        pub fn get(&self, name: &str) -> Option<&Snapshot> {
+            self.presets.get(name)
+        }
+
+        /// Stores `snapshot` under `name`, returning the one it replaces.
+        pub fn insert(&mut self, name: impl Into<String>, snapshot: Snapshot) -> Option<Snapshot> {
+            self.presets.insert(name.into(), snapshot)
+        }
+
+        pub fn remove(&mut self, name: &str) -> Option<Snapshot> {
+            self.presets.remove(name)
+        }
+
+        /// Preset names in alphabetical order.
+        pub fn names(&self) -> impl Iterator<Item = &str> {
+            self.presets.keys().map(String::as_str)
+        }
+
+        /// Writes the presets to the file they were opened from. The file is
+        /// replaced in one go, so a failed save leaves the old one intact.
+        pub fn save(&self) -> io::Result<()> {
+            let json = serde_json::to_vec_pretty(&self.presets)?;
+            let temp = self.path.with_extension("tmp");
+            fs::write(&temp, json)?;
+            fs::rename(&temp, &self.path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{restore_calls, LightSnapshot, Snapshot, BACKGROUND_LIGHT, MAIN_LIGHT};
+    use crate::{
+        connection::{BulbConnection, MethodCallError},
+        lightmode::LightMode,
+        method::Method,
+        model::{Capabilities, BACKGROUND},
+        power::Power,
+        property::ActiveMode,
+        rgb::RGB,
+        test_utils::{bulb_with_methods, ScriptedConnection},
+    };
+
+    fn light(power: Power, color_mode: Option<LightMode>, flow: Option<&str>) -> LightSnapshot {
+        LightSnapshot {
+            power,
+            bright: 60,
+            color_mode,
+            flow_params: flow.map(str::to_string),
+            active_mode: None,
+            nl_br: None,
+        }
+    }
+
+    fn methods(calls: &[super::Call]) -> Vec<Method> {
+        calls.iter().map(|(method, _)| *method).collect()
+    }
+
+    #[test]
+    fn restore_calls_test() {
+        let with_scene: HashSet<Method> = [Method::SetScene, Method::BgSetScene]
+            .iter()
+            .copied()
+            .collect();
+        let red = Some(LightMode::Color(RGB { r: 255, g: 0, b: 0 }));
+        let color = Capabilities::for_model("color").unwrap();
+        let ceiling = Capabilities::for_model("ceiling1").unwrap();
+
+        let off = restore_calls(
+            &light(Power::Off, red.clone(), None),
+            &MAIN_LIGHT,
+            &with_scene,
+            &color,
+        );
+        assert_eq!(methods(&off), vec![Method::SetPower]);
+
+        let on = restore_calls(
+            &light(Power::On, red.clone(), None),
+            &MAIN_LIGHT,
+            &with_scene,
+            &color,
+        );
+        assert_eq!(methods(&on), vec![Method::SetScene]);
+
+        let flowing = light(Power::On, red.clone(), Some("0,1,1000,1,16711680,100"));
+        let calls = restore_calls(&flowing, &BACKGROUND_LIGHT, &with_scene, &BACKGROUND);
+        assert_eq!(methods(&calls), vec![Method::BgSetScene]);
+
+        let without_scene = HashSet::new();
+        let calls = restore_calls(
+            &light(Power::On, red.clone(), None),
+            &MAIN_LIGHT,
+            &without_scene,
+            &color,
+        );
+        assert_eq!(
+            methods(&calls),
+            vec![Method::SetPower, Method::SetRgb, Method::SetBright]
+        );
+        let calls = restore_calls(&flowing, &MAIN_LIGHT, &without_scene, &color);
+        assert_eq!(methods(&calls), vec![Method::SetPower, Method::StartCf]);
+
+        // A mono bulb has no colour to restore.
+        let calls = restore_calls(
+            &light(Power::On, None, None),
+            &MAIN_LIGHT,
+            &with_scene,
+            &ceiling,
+        );
+        assert_eq!(methods(&calls), vec![Method::SetPower, Method::SetBright]);
+
+        // Colours and colour temperatures are checked against what the
+        // light takes.
+        let calls = restore_calls(
+            &light(Power::On, red, None),
+            &MAIN_LIGHT,
+            &with_scene,
+            &ceiling,
+        );
+        assert!(matches!(
+            calls[0].1,
+            Err(MethodCallError::UnsupportedMethod)
+        ));
+        let ct = |ct| light(Power::On, Some(LightMode::ColorTemperature(ct)), None);
+        let calls = restore_calls(&ct(4000), &MAIN_LIGHT, &without_scene, &ceiling);
+        assert_eq!(
+            methods(&calls),
+            vec![Method::SetPower, Method::SetCtAbx, Method::SetBright]
+        );
+        assert!(calls[1].1.is_ok());
+        let calls = restore_calls(&ct(2000), &MAIN_LIGHT, &without_scene, &ceiling);
+        assert!(matches!(calls[1].1, Err(MethodCallError::BadRequest)));
+        let calls = restore_calls(&ct(2000), &BACKGROUND_LIGHT, &without_scene, &BACKGROUND);
+        assert!(calls[1].1.is_ok());
+    }
+
+    #[test]
+    fn restore_moonlight_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":2, \"result\":[\"ok\"]}\r\n",
+        ]);
+        let mut bulb = bulb_with_methods(&[Method::SetScene, Method::SetPower, Method::SetBright]);
+        bulb.model = "ceiling1".to_string();
+        let mut conn = BulbConnection::from_connection(bulb, mock);
+
+        let snapshot = Snapshot {
+            main: LightSnapshot {
+                active_mode: Some(ActiveMode::Moonlight),
+                nl_br: Some(10),
+                ..light(Power::On, Some(LightMode::ColorTemperature(4000)), None)
+            },
+            background: None,
+        };
+        conn.restore(&snapshot).unwrap();
+
+        let written = conn.connection.lock().unwrap().written_str();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "{\"id\":1,\"method\":\"set_power\",\"params\":[\"on\",\"sudden\",50,5]}"
+        );
+        assert!(lines[1].contains("\"set_bright\",\"params\":[10,"));
+
+        // Without a moonlight, night light mode is refused.
+        let calls = restore_calls(
+            &snapshot.main,
+            &MAIN_LIGHT,
+            &HashSet::new(),
+            &Capabilities::for_model("color").unwrap(),
+        );
+        assert!(matches!(
+            calls[0].1,
+            Err(MethodCallError::UnsupportedMethod)
+        ));
+    }
+
+    #[test]
+    fn restore_test() {
+        let mock = ScriptedConnection::new(&[
+            "{\"id\":1, \"result\":[\"ok\"]}\r\n",
+            "{\"id\":2, \"result\":[\"ok\"]}\r\n",
+        ]);
+        let bulb = bulb_with_methods(&[Method::SetScene, Method::BgSetPower]);
+        let mut conn = BulbConnection::from_connection(bulb, mock);
+
+        let snapshot = Snapshot {
+            main: light(Power::On, None, Some("3,0,500,2,4000,80,500,7,0,0")),
+            background: Some(light(Power::Off, None, None)),
+        };
+        conn.restore(&snapshot).unwrap();
+
+        let written = conn.connection.lock().unwrap().written_str();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(
+            lines[0],
+            "{\"id\":1,\"method\":\"set_scene\",\"params\":[\"cf\",3,0,\"500,2,4000,80,500,7,0,0\"]}"
+        );
+        assert!(lines[1].contains("\"bg_set_power\",\"params\":[\"off\""));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn presets_test() {
+        use super::Presets;
+
+        let path = std::env::temp_dir().join(format!("libyee-presets-{}.json", std::process::id()));
+        let snapshot = Snapshot {
+            main: light(Power::On, Some(LightMode::ColorTemperature(2700)), None),
+            background: None,
+        };
+
+        let mut presets = Presets::open(&path).unwrap();
+        assert_eq!(presets.names().count(), 0);
+        presets.insert("evening", snapshot.clone());
+        presets.save().unwrap();
+
+        let presets = Presets::open(&path).unwrap();
+        assert_eq!(presets.names().collect::<Vec<_>>(), vec!["evening"]);
+        assert_eq!(presets.get("evening"), Some(&snapshot));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
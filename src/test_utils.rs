@@ -0,0 +1,67 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    io::{self, Read, Write},
+};
+
+use crate::{bulb::Bulb, lightmode::LightMode, method::Method, power::Power};
+
+/// Hands out the scripted chunks in order, one per `read`, regardless of
+/// what was written. Chunks larger than the read buffer are split.
+pub(crate) struct ScriptedConnection {
+    pub(crate) reads: VecDeque<Vec<u8>>,
+    pub(crate) written: Vec<u8>,
+}
+
+impl ScriptedConnection {
+    pub(crate) fn new(reads: &[&str]) -> Self {
+        ScriptedConnection {
+            reads: reads.iter().map(|r| r.as_bytes().to_vec()).collect(),
+            written: Vec::new(),
+        }
+    }
+
+    pub(crate) fn written_str(&self) -> String {
+        String::from_utf8_lossy(&self.written).to_string()
+    }
+}
+
+impl Read for ScriptedConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.reads.pop_front() {
+            Some(mut chunk) => {
+                let len = usize::min(chunk.len(), buf.len());
+                buf[..len].copy_from_slice(&chunk[..len]);
+                if len < chunk.len() {
+                    self.reads.push_front(chunk.split_off(len));
+                }
+                io::Result::Ok(len)
+            }
+            None => io::Result::Ok(0),
+        }
+    }
+}
+
+impl Write for ScriptedConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.extend_from_slice(buf);
+        io::Result::Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::Result::Ok(())
+    }
+}
+
+pub(crate) fn bulb_with_methods(methods: &[Method]) -> Bulb {
+    Bulb {
+        id: "".to_string(),
+        model: "".to_string(),
+        fw_ver: "".to_string(),
+        support: methods.iter().cloned().collect::<HashSet<Method>>(),
+        power: Power::Off,
+        bright: 0,
+        color_mode: LightMode::ColorTemperature(8),
+        name: "".to_string(),
+        ip_address: "".to_string(),
+    }
+}